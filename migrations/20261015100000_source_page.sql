@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN source_page INT;
@@ -0,0 +1,6 @@
+CREATE TABLE scrape_errors (
+    rowid INTEGER PRIMARY KEY NOT NULL,
+    post_id VARCHAR,
+    source_page INT NOT NULL,
+    reason VARCHAR NOT NULL
+);
@@ -0,0 +1,4 @@
+CREATE INDEX post_links_post_id ON post_links (post_id);
+CREATE INDEX post_links_status ON post_links ("status", post_id);
+CREATE INDEX posts_created_at ON posts (created_at);
+CREATE INDEX posts_creator ON posts (creator);
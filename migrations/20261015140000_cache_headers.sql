@@ -0,0 +1,2 @@
+ALTER TABLE post_links ADD COLUMN etag VARCHAR;
+ALTER TABLE post_links ADD COLUMN last_modified VARCHAR;
@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN scraped_at VARCHAR;
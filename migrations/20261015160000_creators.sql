@@ -0,0 +1,8 @@
+CREATE TABLE creators (
+    name VARCHAR PRIMARY KEY NOT NULL,
+    hutt_id INT NOT NULL,
+    bio VARCHAR,
+    avatar_path VARCHAR,
+    banner_path VARCHAR,
+    updated_at VARCHAR NOT NULL
+);
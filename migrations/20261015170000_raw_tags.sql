@@ -0,0 +1 @@
+ALTER TABLE posts ADD COLUMN raw_tags VARCHAR;
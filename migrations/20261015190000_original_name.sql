@@ -0,0 +1 @@
+ALTER TABLE post_links ADD COLUMN original_name VARCHAR;
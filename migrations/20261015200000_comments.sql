@@ -0,0 +1,7 @@
+CREATE TABLE comments (
+    rowid INTEGER PRIMARY KEY NOT NULL,
+    post_id INTEGER NOT NULL REFERENCES posts(id),
+    author VARCHAR NOT NULL,
+    text VARCHAR NOT NULL,
+    UNIQUE (post_id, author, text)
+);
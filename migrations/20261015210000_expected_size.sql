@@ -0,0 +1 @@
+ALTER TABLE post_links ADD COLUMN expected_size INTEGER;
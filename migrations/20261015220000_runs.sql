@@ -0,0 +1,7 @@
+CREATE TABLE runs (
+    rowid INTEGER PRIMARY KEY NOT NULL,
+    command VARCHAR NOT NULL,
+    started_at VARCHAR NOT NULL,
+    duration_seconds REAL NOT NULL,
+    items INT NOT NULL
+);
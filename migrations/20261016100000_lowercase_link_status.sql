@@ -0,0 +1,4 @@
+UPDATE post_links SET "status" = 'pending' WHERE "status" = 'Pending';
+UPDATE post_links SET "status" = 'downloaded' WHERE "status" = 'Downloaded';
+UPDATE post_links SET "status" = 'error' WHERE "status" = 'Error';
+UPDATE post_links SET "status" = 'skipped_too_large' WHERE "status" = 'SkippedTooLarge';
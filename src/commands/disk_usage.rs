@@ -0,0 +1,149 @@
+use std::collections::{BTreeMap, HashMap};
+
+use indicatif::HumanBytes;
+use tracing::warn;
+
+use crate::database::{LinkStatus, Post, PostType};
+use crate::{DownloadContext, Result};
+
+pub struct DiskUsageArgs {
+    /// How many of the largest posts to list.
+    pub top: usize,
+}
+
+#[derive(Debug, PartialEq)]
+struct PostUsage {
+    id: i64,
+    title: String,
+    bytes: u64,
+}
+
+#[derive(Debug, Default)]
+struct DiskUsage {
+    by_type: HashMap<PostType, u64>,
+    by_creator: BTreeMap<String, u64>,
+    posts: Vec<PostUsage>,
+    missing_files: usize,
+}
+
+impl DiskUsage {
+    fn add(&mut self, post: &Post, bytes: u64) {
+        *self.by_type.entry(post.post_type).or_default() += bytes;
+        *self.by_creator.entry(post.creator.clone()).or_default() += bytes;
+        self.posts.push(PostUsage {
+            id: post.id,
+            title: post.title.clone(),
+            bytes,
+        });
+    }
+
+    fn total(&self) -> u64 {
+        self.by_type.values().sum()
+    }
+
+    /// The `n` posts that take up the most space, largest first.
+    fn largest(&mut self, n: usize) -> &[PostUsage] {
+        self.posts
+            .sort_by(|a, b| b.bytes.cmp(&a.bytes).then(a.id.cmp(&b.id)));
+        &self.posts[..n.min(self.posts.len())]
+    }
+}
+
+pub async fn run(context: DownloadContext, args: DiskUsageArgs) -> Result<()> {
+    let mut posts = context.database.stream_all();
+    let mut usage = DiskUsage::default();
+
+    while let Some(post) = posts.next_post().await? {
+        let mut bytes = 0;
+        for link in &post.links {
+            if link.status != LinkStatus::Downloaded {
+                continue;
+            }
+            let Some(path) = &link.file_path else {
+                continue;
+            };
+            match tokio::fs::metadata(path).await {
+                Ok(metadata) => bytes += metadata.len(),
+                Err(e) => {
+                    warn!("failed to read size of {path}: {e}");
+                    usage.missing_files += 1;
+                }
+            }
+        }
+        if bytes > 0 {
+            usage.add(&post, bytes);
+        }
+    }
+
+    println!("Total: {}", HumanBytes(usage.total()));
+    println!();
+    println!("By type:");
+    for post_type in [PostType::Image, PostType::Video] {
+        let bytes = usage.by_type.get(&post_type).copied().unwrap_or_default();
+        println!("  {post_type:?}: {}", HumanBytes(bytes));
+    }
+    println!();
+    println!("By creator:");
+    for (creator, bytes) in &usage.by_creator {
+        println!("  {creator}: {}", HumanBytes(*bytes));
+    }
+    if args.top > 0 {
+        println!();
+        println!("Largest posts:");
+        for post in usage.largest(args.top) {
+            println!("  {} ({}): {}", post.id, post.title, HumanBytes(post.bytes));
+        }
+    }
+    if usage.missing_files > 0 {
+        println!();
+        println!(
+            "{} downloaded files are missing on disk, run `report` to investigate",
+            usage.missing_files
+        );
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::DiskUsage;
+    use crate::database::{Post, PostType};
+
+    fn post(id: i64, creator: &str, post_type: PostType) -> Post {
+        Post {
+            id,
+            title: format!("post {id}"),
+            creator: creator.into(),
+            tags: vec![],
+            post_type,
+            like_count: 0,
+            links: vec![],
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        }
+    }
+
+    #[test]
+    fn test_disk_usage() {
+        let mut usage = DiskUsage::default();
+        usage.add(&post(1, "a", PostType::Image), 100);
+        usage.add(&post(2, "a", PostType::Video), 5000);
+        usage.add(&post(3, "b", PostType::Image), 300);
+
+        assert_eq!(usage.total(), 5400);
+        assert_eq!(usage.by_type[&PostType::Image], 400);
+        assert_eq!(usage.by_type[&PostType::Video], 5000);
+        assert_eq!(usage.by_creator["a"], 5100);
+        assert_eq!(usage.by_creator["b"], 300);
+
+        let largest: Vec<_> = usage.largest(2).iter().map(|post| post.id).collect();
+        assert_eq!(largest, vec![2, 3]);
+        assert_eq!(usage.largest(10).len(), 3);
+    }
+}
@@ -0,0 +1,100 @@
+use color_eyre::eyre::bail;
+use reqwest::StatusCode;
+use tokio::process::Command;
+
+use crate::commands::metadata::USER_AGENT;
+use crate::{DownloadContext, Result};
+
+async fn check_yt_dlp() -> Result<String> {
+    let output = Command::new("yt-dlp").arg("--version").output().await?;
+    if !output.status.success() {
+        bail!("`yt-dlp --version` exited with {}", output.status);
+    }
+    let version = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    Ok(format!("yt-dlp {version}"))
+}
+
+async fn check_database(context: &DownloadContext) -> Result<String> {
+    let applied = context.database.applied_migrations().await?;
+    let pending: Vec<_> = sqlx::migrate!()
+        .iter()
+        .filter(|migration| !applied.contains(&migration.version))
+        .map(|migration| migration.description.to_string())
+        .collect();
+
+    if pending.is_empty() {
+        Ok(format!("{} migrations applied", applied.len()))
+    } else {
+        bail!("pending migrations: {}", pending.join(", "))
+    }
+}
+
+async fn check_download_directory(context: &DownloadContext) -> Result<String> {
+    let directory = context.configuration.download_directory();
+    tokio::fs::create_dir_all(directory).await?;
+
+    let probe = directory.join(".hutt-doctor");
+    tokio::fs::write(&probe, b"ok").await?;
+    tokio::fs::remove_file(&probe).await?;
+
+    Ok(format!("{directory} is writable"))
+}
+
+async fn check_cookie(context: &DownloadContext) -> Result<String> {
+    let configuration = &context.configuration;
+    let url = format!(
+        "https://hutt.co/hutts/ajax-posts?page=0&view=view&id={}",
+        configuration.creator_id
+    );
+    let response = context
+        .client
+        .get(&url)
+        .header("Cookie", &configuration.cookie)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?;
+
+    let status = response.status();
+    if status == StatusCode::UNAUTHORIZED || status == StatusCode::FORBIDDEN {
+        bail!("cookie was rejected with status {status}");
+    }
+    if response.url().path().contains("login") {
+        bail!("redirected to the login page, the cookie is probably expired");
+    }
+    let response = response.error_for_status()?;
+    let text = response.text().await?;
+    if text.trim().is_empty() {
+        bail!("no posts returned for creator {}", configuration.creator_id);
+    }
+
+    Ok(format!(
+        "authenticated as a viewer of creator {}",
+        configuration.creator_id
+    ))
+}
+
+fn print_check(name: &str, result: &Result<String>) {
+    match result {
+        Ok(message) => println!("\x1b[32m✓\x1b[0m {name}: {message}"),
+        Err(e) => println!("\x1b[31m✗\x1b[0m {name}: {e}"),
+    }
+}
+
+/// Runs all checks and returns whether every one of them passed.
+pub async fn run(context: DownloadContext) -> Result<bool> {
+    let checks = [
+        ("yt-dlp", check_yt_dlp().await),
+        ("database", check_database(&context).await),
+        (
+            "download directory",
+            check_download_directory(&context).await,
+        ),
+        ("cookie", check_cookie(&context).await),
+    ];
+
+    for (name, result) in &checks {
+        print_check(name, result);
+    }
+
+    Ok(checks.iter().all(|(_, result)| result.is_ok()))
+}
@@ -1,20 +1,38 @@
 use std::collections::HashMap;
+use std::io::IsTerminal;
+use std::pin::pin;
 use std::process::Stdio;
-use std::time::Duration;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
 use camino::{Utf8Path, Utf8PathBuf};
+use chrono::Utc;
+use clap::ValueEnum;
 use color_eyre::eyre::bail;
-use indicatif::{ProgressBar, ProgressStyle};
+use dialoguer::MultiSelect;
+use futures_util::future::{self, join_all};
+use futures_util::{stream, StreamExt};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressStyle};
+use regex::{Regex, RegexBuilder};
+use reqwest::header::{CONTENT_LENGTH, ETAG, IF_MODIFIED_SINCE, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::StatusCode;
+use serde::Serialize;
 use tokio::io::AsyncWriteExt;
-use tracing::{debug, info};
+use tracing::{debug, info, warn};
 
-use crate::commands::metadata::USER_AGENT;
-use crate::database::{LinkStatus, PostLink, PostType, StatusUpdate};
-use crate::filenames::get_download_path;
-use crate::{DownloadContext, Result};
+use crate::commands::metadata::{retry_after, USER_AGENT};
+use crate::database::{LinkStatus, Post, PostLink, PostType, RunStats, StatusUpdate};
+use crate::filenames::{bucket_directory, get_download_path, DateBucket, FLAT_IMAGE_PATTERN};
+use crate::{DownloadContext, Downloader, Result};
 
 const BASE_URL: &str = "https://hutt.co";
 
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum PostOrder {
+    Oldest,
+    Newest,
+}
+
 #[derive(Debug)]
 pub struct DownloadArgs {
     pub filename_pattern: HashMap<PostType, String>,
@@ -22,29 +40,391 @@ pub struct DownloadArgs {
     pub dry_run: bool,
     pub progress: bool,
     pub fail_fast: bool,
+    pub order: Option<PostOrder>,
+    pub max_size: Option<u64>,
+    pub quota: Option<u64>,
+    pub check_updates: bool,
+    /// Prints one JSON object per processed link to stdout.
+    pub json: bool,
+    pub image_concurrency: usize,
+    pub video_concurrency: usize,
+    pub title_filter: TitleFilter,
+    /// Only retries links that failed before and leaves pending ones alone.
+    pub retry_errors: bool,
+    /// Detects the type of downloaded images from their contents instead of trusting the
+    /// scraped content type.
+    pub sniff_content_type: bool,
+    /// Sorts the files into date directories below the download directory.
+    pub bucket_by: Option<DateBucket>,
+    /// Doesn't start new downloads once this much time has passed since the start of the run.
+    pub deadline: Option<Duration>,
+    /// Looks up the size of every file before downloading, so progress is shown in bytes.
+    pub accurate_progress: bool,
+    /// Lets the user pick the posts to download from a list.
+    pub select: bool,
+    /// Puts all images into one directory with numbered names instead of a directory per post.
+    pub flatten: bool,
+}
+
+impl DownloadArgs {
+    /// The filename pattern for posts of the given type, taking `--flatten` into account.
+    fn pattern(&self, post_type: PostType) -> &str {
+        match post_type {
+            PostType::Image if self.flatten => FLAT_IMAGE_PATTERN,
+            _ => &self.filename_pattern[&post_type],
+        }
+    }
+}
+
+/// Limits a download run to posts whose titles match one pattern and don't match another.
+#[derive(Debug, Default)]
+pub struct TitleFilter {
+    include: Option<Regex>,
+    exclude: Option<Regex>,
+}
+
+impl TitleFilter {
+    pub fn new(include: Option<&str>, exclude: Option<&str>, case_sensitive: bool) -> Result<Self> {
+        let build = |pattern: &str| {
+            RegexBuilder::new(pattern)
+                .case_insensitive(!case_sensitive)
+                .build()
+        };
+        Ok(Self {
+            include: include.map(build).transpose()?,
+            exclude: exclude.map(build).transpose()?,
+        })
+    }
+
+    pub fn matches(&self, title: &str) -> bool {
+        let included = match &self.include {
+            Some(regex) => regex.is_match(title),
+            None => true,
+        };
+        included
+            && !self
+                .exclude
+                .as_ref()
+                .is_some_and(|regex| regex.is_match(title))
+    }
+}
+
+/// Checks that a `--title-match` or `--title-exclude` pattern is a valid regex.
+pub fn parse_regex(input: &str) -> std::result::Result<String, String> {
+    Regex::new(input).map_err(|e| e.to_string())?;
+    Ok(input.to_string())
+}
+
+/// How a post is shown in the `--select` list.
+fn post_label(post: &Post) -> String {
+    let title: String = post.title.split_whitespace().collect::<Vec<_>>().join(" ");
+    let title: String = title.chars().take(60).collect();
+    let mut label = format!("{} [{:?}] {title}", post.id, post.post_type);
+    if !post.tags.is_empty() {
+        let tags: Vec<_> = post.tags.iter().map(|tag| format!("#{tag}")).collect();
+        label.push_str(&format!(" ({})", tags.join(" ")));
+    }
+    label
+}
+
+/// Asks which of `posts` to download. Without a terminal to ask in, all of them are kept.
+fn select_posts(posts: Vec<Post>) -> Result<Vec<Post>> {
+    if !std::io::stdin().is_terminal() || !std::io::stderr().is_terminal() {
+        warn!(
+            "--select needs a terminal, downloading all {} posts",
+            posts.len()
+        );
+        return Ok(posts);
+    }
+    if posts.is_empty() {
+        return Ok(posts);
+    }
+
+    let labels: Vec<_> = posts.iter().map(post_label).collect();
+    let selected = MultiSelect::new()
+        .with_prompt("Posts to download (space to select, enter to confirm)")
+        .items(&labels)
+        .max_length(20)
+        .interact_opt()?
+        .unwrap_or_default();
+    info!("selected {} of {} posts", selected.len(), posts.len());
+
+    let mut selected = selected.into_iter().peekable();
+    Ok(posts
+        .into_iter()
+        .enumerate()
+        .filter(|(index, _)| selected.next_if_eq(index).is_some())
+        .map(|(_, post)| post)
+        .collect())
+}
+
+/// The progress bars of a download run: one counting links, one counting bytes as they arrive.
+/// With a known total size the bytes bar shows how much is left instead of just a spinner.
+struct Progress {
+    links: ProgressBar,
+    bytes: ProgressBar,
+    // keeps both bars drawn together
+    _multi: Option<MultiProgress>,
+}
+
+impl Progress {
+    fn new(enabled: bool, link_count: u64, total_bytes: Option<u64>) -> Self {
+        if !enabled {
+            return Self {
+                links: ProgressBar::hidden(),
+                bytes: ProgressBar::hidden(),
+                _multi: None,
+            };
+        }
+
+        let multi = MultiProgress::new();
+        let links = multi.add(ProgressBar::new(link_count));
+        links.set_style(
+            ProgressStyle::with_template(
+                "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} ETA {eta} {msg}",
+            )
+            .unwrap(),
+        );
+        let bytes = match total_bytes {
+            Some(total_bytes) => {
+                let bytes = multi.add(ProgressBar::new(total_bytes));
+                bytes.set_style(
+                    ProgressStyle::with_template(
+                        "[{elapsed_precise}] {bar:40.green/white} {binary_bytes}/{binary_total_bytes} at {binary_bytes_per_sec} ETA {eta} {msg}",
+                    )
+                    .unwrap(),
+                );
+                bytes
+            }
+            None => {
+                let bytes = multi.add(ProgressBar::new_spinner());
+                bytes.set_style(
+                    ProgressStyle::with_template(
+                        "{spinner} {binary_bytes} downloaded at {binary_bytes_per_sec} {msg}",
+                    )
+                    .unwrap(),
+                );
+                bytes
+            }
+        };
+
+        Self {
+            links,
+            bytes,
+            _multi: Some(multi),
+        }
+    }
+}
+
+/// Exit code used when the run completed but some links failed to download.
+pub const PARTIAL_FAILURE_EXIT_CODE: i32 = 2;
+
+#[derive(Debug, Default)]
+pub struct DownloadStats {
+    pub processed: usize,
+    pub downloaded: usize,
+    pub errors: usize,
+}
+
+/// Outcome of a single link, printed as a JSON line with `--json`.
+#[derive(Debug, Serialize)]
+struct LinkResult {
+    post_id: i64,
+    link_id: i64,
+    status: &'static str,
+    path: Option<Utf8PathBuf>,
+    bytes: Option<u64>,
+    error: Option<String>,
+    /// The original error of a failed download, returned as-is when failing fast.
+    #[serde(skip)]
+    report: Option<color_eyre::Report>,
+}
+
+impl LinkResult {
+    fn print(&self, json: bool) -> Result<()> {
+        if json {
+            println!("{}", serde_json::to_string(self)?);
+        }
+        Ok(())
+    }
+}
+
+/// Validators the server sent along with a file, used for conditional requests later on.
+#[derive(Debug, Default)]
+struct CacheHeaders {
+    etag: Option<String>,
+    last_modified: Option<String>,
+}
+
+impl CacheHeaders {
+    fn from_response(response: &reqwest::Response) -> Self {
+        let header = |name| {
+            response
+                .headers()
+                .get(name)
+                .and_then(|value| value.to_str().ok())
+                .map(ToOwned::to_owned)
+        };
+        Self {
+            etag: header(ETAG),
+            last_modified: header(LAST_MODIFIED),
+        }
+    }
+}
+
+enum DownloadOutcome {
+    /// The file was written to `path`, which can differ from the requested one in its extension.
+    Downloaded {
+        path: Utf8PathBuf,
+        cache_headers: CacheHeaders,
+    },
+    NotModified,
+    /// yt-dlp skipped the video because its download archive says it was downloaded before.
+    AlreadyArchived,
+    SkippedTooLarge,
+}
+
+/// Parses a size like `500M` or `2G` (binary units) into bytes.
+pub fn parse_size(input: &str) -> std::result::Result<u64, String> {
+    let input = input.trim();
+    let split = input
+        .find(|c: char| !c.is_ascii_digit() && c != '.')
+        .unwrap_or(input.len());
+    let (number, unit) = input.split_at(split);
+    let number: f64 = number
+        .parse()
+        .map_err(|_| format!("invalid size `{input}`"))?;
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        other => return Err(format!("unknown size unit `{other}`")),
+    };
+    Ok((number * multiplier as f64) as u64)
+}
+
+/// Parses a duration like `90s`, `45m`, `2h` or `1h30m`.
+pub fn parse_duration(input: &str) -> std::result::Result<Duration, String> {
+    let input = input.trim();
+    if input.is_empty() {
+        return Err("empty duration".to_string());
+    }
+    let mut rest = input;
+    let mut seconds = 0;
+    while !rest.is_empty() {
+        let split = rest
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(rest.len());
+        let (number, tail) = rest.split_at(split);
+        let number: u64 = number
+            .parse()
+            .map_err(|_| format!("invalid duration `{input}`"))?;
+        let split = tail
+            .find(|c: char| c.is_ascii_digit())
+            .unwrap_or(tail.len());
+        let (unit, tail) = tail.split_at(split);
+        let multiplier = match unit.trim().to_ascii_lowercase().as_str() {
+            "s" | "sec" => 1,
+            "m" | "min" => 60,
+            "h" => 60 * 60,
+            "d" => 24 * 60 * 60,
+            "" => return Err(format!("missing unit in duration `{input}`, e.g. `30m`")),
+            other => return Err(format!("unknown duration unit `{other}`")),
+        };
+        seconds += number * multiplier;
+        rest = tail;
+    }
+    Ok(Duration::from_secs(seconds))
+}
+
+/// Marks the line yt-dlp prints with the path of the file it wrote. That's usually the `.part`
+/// path passed with `-o`, but yt-dlp replaces the extension when the merged format differs.
+const FILEPATH_PREFIX: &str = "hutt-archiver-file:";
+
+/// Path that a download is written to until it's complete, so that a file at the final path is
+/// always fully downloaded.
+fn partial_path(file: &Utf8Path) -> Utf8PathBuf {
+    Utf8PathBuf::from(format!("{file}.part"))
+}
+
+/// Runs the configured post-download hook for a finished file. The template is split on
+/// whitespace and the placeholders are substituted per argument, so paths with spaces stay intact.
+async fn run_post_download_hook(
+    hook: &str,
+    file: &Utf8Path,
+    post_id: i64,
+    url: &str,
+) -> Result<()> {
+    use tokio::process::Command;
+
+    let post_id = post_id.to_string();
+    let url = format!("{BASE_URL}{url}");
+    let mut parts = hook.split_whitespace().map(|part| {
+        part.replace("{file}", file.as_str())
+            .replace("{post_id}", &post_id)
+            .replace("{url}", &url)
+    });
+    let Some(program) = parts.next() else {
+        bail!("post-download hook is empty");
+    };
+
+    let status = Command::new(&program)
+        .args(parts)
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit())
+        .status()
+        .await?;
+    if !status.success() {
+        bail!("post-download hook `{program}` for {file} exited with {status}");
+    }
+    Ok(())
 }
 
 async fn download_video(
     context: &DownloadContext,
     link: &PostLink,
     file: impl AsRef<Utf8Path>,
-) -> Result<()> {
+    max_size: Option<u64>,
+    referer: &str,
+    progress: &ProgressBar,
+) -> Result<DownloadOutcome> {
+    use tokio::io::{AsyncBufReadExt, BufReader};
     use tokio::process::Command;
 
     let directory = file.as_ref().parent().unwrap();
     tokio::fs::create_dir_all(directory).await?;
 
-    let file_name = file.as_ref().file_name().unwrap();
-
-    let referer = format!("https://hutt.co/{}", context.configuration.creator_name);
+    // yt-dlp writes to the partial path, which is only renamed once it exits successfully
+    let part_file = partial_path(file.as_ref());
+    let part_file_name = part_file.file_name().unwrap();
 
     let url = format!("{}{}", BASE_URL, link.url);
     info!("video link: {}", url);
-    let mut command = Command::new("yt-dlp")
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .arg("--add-header")
-        .arg(format!("Cookie: {}", context.configuration.cookie))
+    let mut command = Command::new("yt-dlp");
+    if let Some(max_size) = max_size {
+        command.arg("--max-filesize").arg(max_size.to_string());
+    }
+    if let Some(proxy) = &context.configuration.proxy {
+        command.arg("--proxy").arg(proxy);
+    }
+    if let Some(archive) = &context.configuration.yt_dlp_archive {
+        command.arg("--download-archive").arg(archive);
+    }
+    // yt-dlp reads the browser's cookies itself, which keeps them fresh during long runs
+    match context.configuration.cookie_source {
+        Some(source) => command
+            .arg("--cookies-from-browser")
+            .arg(source.browser().name()),
+        None => command
+            .arg("--add-header")
+            .arg(format!("Cookie: {}", context.configuration.cookie)),
+    };
+    let mut child = command
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .arg("--newline")
         .arg("--add-header")
         .arg(format!("User-Agent: {}", USER_AGENT))
         .arg("--add-header")
@@ -55,141 +435,1009 @@ async fn download_video(
         .arg("3")
         .arg("--retry-sleep")
         .arg("120")
+        // `--print` implies `--quiet`, which would hide the progress and archive messages
+        .arg("--no-quiet")
+        .arg("--print")
+        .arg(format!("after_move:{FILEPATH_PREFIX}%(filepath)s"))
         .arg("-o")
-        .arg(file_name)
+        .arg(part_file_name)
         .arg(&url)
         .current_dir(directory)
+        .kill_on_drop(true)
         .spawn()?;
 
-    let result = command.wait().await?;
+    // route yt-dlp's output through the log and the progress bar instead of the terminal
+    let stdout = child.stdout.take().unwrap();
+    let stderr = child.stderr.take().unwrap();
+    let read_stdout = async {
+        let mut lines = BufReader::new(stdout).lines();
+        let mut archived = false;
+        let mut produced = None;
+        while let Some(line) = lines.next_line().await? {
+            debug!("yt-dlp: {}", line);
+            if let Some(percent) = parse_progress(&line) {
+                progress.set_message(format!("Downloading {} ({percent:.1}%)", file.as_ref()));
+            }
+            if let Some(path) = line.strip_prefix(FILEPATH_PREFIX) {
+                produced = Some(directory.join(path));
+            }
+            archived |= line.contains("has already been recorded in the archive");
+        }
+        Ok::<_, std::io::Error>((archived, produced))
+    };
+    let read_stderr = async {
+        let mut lines = BufReader::new(stderr).lines();
+        let mut captured = vec![];
+        while let Some(line) = lines.next_line().await? {
+            warn!("yt-dlp: {}", line);
+            captured.push(line);
+        }
+        Ok::<_, std::io::Error>(captured)
+    };
+    let (stdout_result, stderr_result) = tokio::join!(read_stdout, read_stderr);
+    let (archived, produced) = stdout_result?;
+    let stderr_lines = stderr_result?;
+
+    let result = child.wait().await?;
     if !result.success() {
-        bail!("failed to download {} with exit code {}", link.url, result);
-    } else {
-        info!("downloaded {} to {}", url, directory);
+        const MAX_ERROR_LINES: usize = 5;
+        let start = stderr_lines.len().saturating_sub(MAX_ERROR_LINES);
+        bail!(
+            "failed to download {} with exit code {}: {}",
+            link.url,
+            result,
+            stderr_lines[start..].join("\n")
+        );
+    }
+    if archived {
+        info!("{} is already in the yt-dlp archive, skipping", url);
+        return Ok(DownloadOutcome::AlreadyArchived);
     }
 
-    Ok(())
+    let produced = match produced {
+        Some(produced) => produced,
+        // yt-dlp exits successfully without writing anything when the file exceeds --max-filesize
+        None if max_size.is_some() => {
+            info!("skipped {} because it exceeds the maximum size", url);
+            return Ok(DownloadOutcome::SkippedTooLarge);
+        }
+        None => bail!("yt-dlp exited successfully but didn't report an output file for {url}"),
+    };
+    // keep the extension yt-dlp picked, e.g. `.mkv` for formats that can't be muxed into mp4
+    let path = match produced.extension() {
+        Some("part") | None => file.as_ref().to_owned(),
+        Some(extension) => file.as_ref().with_extension(extension),
+    };
+    tokio::fs::rename(&produced, &path).await?;
+    info!("downloaded {} to {}", url, path);
+
+    Ok(DownloadOutcome::Downloaded {
+        path,
+        cache_headers: CacheHeaders::default(),
+    })
+}
+
+/// Parses the percentage out of a yt-dlp progress line like
+/// `[download]  42.3% of ~ 10.00MiB at  1.00MiB/s ETA 00:05`.
+fn parse_progress(line: &str) -> Option<f64> {
+    let rest = line.strip_prefix("[download]")?;
+    let percent = rest.split_whitespace().next()?.strip_suffix('%')?;
+    percent.parse().ok()
+}
+
+/// Detects the type of a file from its first bytes.
+async fn sniff_type(path: &Utf8Path) -> Result<Option<infer::Type>> {
+    use tokio::io::AsyncReadExt;
+
+    let mut file = tokio::fs::File::open(path).await?;
+    let mut header = Vec::with_capacity(8192);
+    (&mut file).take(8192).read_to_end(&mut header).await?;
+    Ok(infer::get(&header))
+}
+
+/// Whether `path` already has `extension`, treating `jpg` and `jpeg` as the same.
+fn has_extension(path: &Utf8Path, extension: &str) -> bool {
+    let normalize = |extension: &str| match extension.to_ascii_lowercase().as_str() {
+        "jpg" => "jpeg".to_string(),
+        other => other.to_string(),
+    };
+    path.extension()
+        .is_some_and(|current| normalize(current) == normalize(extension))
 }
 
 async fn download_images(
     context: &DownloadContext,
     link: &PostLink,
     file: impl AsRef<Utf8Path>,
-) -> Result<()> {
-    use tokio::fs::File;
+    max_size: Option<u64>,
+    conditional: bool,
+    sniff: bool,
+    bytes: &ProgressBar,
+) -> Result<DownloadOutcome> {
+    use tokio::fs::{File, OpenOptions};
 
     let directory = file.as_ref().parent().unwrap();
     tokio::fs::create_dir_all(directory).await?;
 
     let url = format!("{}{}", BASE_URL, link.url);
-    let mut response = context
-        .client
-        .get(&url)
-        .header("Cookie", &context.configuration.cookie)
-        .header("User-Agent", USER_AGENT)
-        .send()
-        .await?
-        .error_for_status()?;
+    let part_file = partial_path(file.as_ref());
+    let mut existing_len = tokio::fs::metadata(&part_file)
+        .await
+        .map(|m| m.len())
+        .unwrap_or(0);
+
+    let mut rate_limited = 0;
+    let mut response = loop {
+        let mut request = context
+            .client
+            .get(&url)
+            .header("Cookie", &context.configuration.cookie)
+            .header("User-Agent", USER_AGENT);
+        if existing_len > 0 {
+            info!("resuming {} from byte {}", url, existing_len);
+            request = request.header("Range", format!("bytes={existing_len}-"));
+        }
+        if conditional {
+            if let Some(etag) = &link.etag {
+                request = request.header(IF_NONE_MATCH, etag);
+            }
+            if let Some(last_modified) = &link.last_modified {
+                request = request.header(IF_MODIFIED_SINCE, last_modified);
+            }
+        }
+        let response = request.send().await?;
+        if response.status() == StatusCode::NOT_MODIFIED {
+            info!("{} has not changed since it was downloaded", url);
+            return Ok(DownloadOutcome::NotModified);
+        }
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            let max_retries = context.configuration.max_rate_limit_retries();
+            if rate_limited >= max_retries {
+                bail!("still rate limited on {url} after {max_retries} retries, giving up");
+            }
+            rate_limited += 1;
+            let wait = retry_after(&response);
+            warn!(
+                "rate limited on {}, retrying in {} seconds",
+                url,
+                wait.as_secs()
+            );
+            tokio::time::sleep(wait).await;
+            continue;
+        }
+        if response.status() == StatusCode::RANGE_NOT_SATISFIABLE && existing_len > 0 {
+            warn!("server rejected range for {}, restarting download", url);
+            existing_len = 0;
+            continue;
+        }
+        break response.error_for_status()?;
+    };
+    let cache_headers = CacheHeaders::from_response(&response);
+
+    if let (Some(max_size), Some(remaining)) = (max_size, response.content_length()) {
+        let total = if response.status() == StatusCode::PARTIAL_CONTENT {
+            existing_len + remaining
+        } else {
+            remaining
+        };
+        if total > max_size {
+            info!("skipped {} because it is {} bytes large", url, total);
+            return Ok(DownloadOutcome::SkippedTooLarge);
+        }
+    }
     info!(
         "downloaded {} with status {} to {}",
         url,
         response.status(),
         file.as_ref()
     );
-    let mut file = File::create(file.as_ref()).await?;
+
+    let mut output = if response.status() == StatusCode::PARTIAL_CONTENT {
+        OpenOptions::new().append(true).open(&part_file).await?
+    } else {
+        File::create(&part_file).await?
+    };
     while let Some(chunk) = response.chunk().await? {
-        file.write_all(&chunk).await?;
+        output.write_all(&chunk).await?;
+        bytes.inc(chunk.len() as u64);
     }
+    output.flush().await?;
+    drop(output);
 
-    Ok(())
+    let path = finish_download(context, link, &part_file, file.as_ref(), sniff).await?;
+    Ok(DownloadOutcome::Downloaded {
+        path,
+        cache_headers,
+    })
 }
 
-pub async fn run(context: DownloadContext, args: DownloadArgs) -> Result<()> {
-    let posts = context.database.fetch_all().await?;
-    let posts: Vec<_> = posts
-        .into_iter()
-        .filter(|post| {
-            post.links
-                .iter()
-                .any(|link| link.status != LinkStatus::Downloaded)
+/// Moves a complete download from its partial path to `file` and returns where it ended up. With
+/// `sniff`, the extension and stored content type are fixed to match the contents.
+async fn finish_download(
+    context: &DownloadContext,
+    link: &PostLink,
+    part_file: &Utf8Path,
+    file: &Utf8Path,
+    sniff: bool,
+) -> Result<Utf8PathBuf> {
+    let mut path = file.to_owned();
+    if sniff {
+        if let Some(kind) = sniff_type(part_file).await? {
+            if kind.mime_type() != link.content_type {
+                info!(
+                    "{} is {}, not {} as scraped",
+                    link.url,
+                    kind.mime_type(),
+                    link.content_type
+                );
+                context
+                    .database
+                    .update_content_type(link.id, kind.mime_type())
+                    .await?;
+            }
+            if !has_extension(&path, kind.extension()) {
+                path.set_extension(kind.extension());
+                info!("saving {} as {} to match its contents", link.url, path);
+            }
+        }
+    }
+    tokio::fs::rename(part_file, &path).await?;
+
+    Ok(path)
+}
+
+/// Fails early with a helpful message if `program` can't be run at all.
+async fn check_binary(program: &str) -> Result<()> {
+    let status = tokio::process::Command::new(program)
+        .arg("--version")
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .await;
+    match status {
+        Ok(status) if status.success() => Ok(()),
+        Ok(status) => bail!("`{program} --version` exited with {status}"),
+        Err(e) => bail!("{program} is configured as the downloader but can't be run ({e}), install it or remove `downloader` from the configuration"),
+    }
+}
+
+/// The arguments to download `url` with aria2c to `file` inside `directory`, resuming a partial
+/// download if there is one.
+fn aria2c_args(
+    url: &str,
+    directory: &Utf8Path,
+    file: &str,
+    cookie: &str,
+    proxy: Option<&str>,
+) -> Vec<String> {
+    let mut args = vec![
+        "--dir".to_string(),
+        directory.to_string(),
+        "--out".to_string(),
+        file.to_string(),
+        "--header".to_string(),
+        format!("Cookie: {cookie}"),
+        "--header".to_string(),
+        format!("User-Agent: {USER_AGENT}"),
+        "--max-connection-per-server=4".to_string(),
+        "--split=4".to_string(),
+        "--continue=true".to_string(),
+        "--auto-file-renaming=false".to_string(),
+        "--allow-overwrite=true".to_string(),
+        "--file-allocation=none".to_string(),
+        "--summary-interval=0".to_string(),
+        "--console-log-level=warn".to_string(),
+    ];
+    if let Some(proxy) = proxy {
+        args.push(format!("--all-proxy={proxy}"));
+    }
+    args.push(url.to_string());
+    args
+}
+
+/// Downloads a direct file with aria2c instead of reqwest, for more throughput on large files.
+async fn download_with_aria2c(
+    context: &DownloadContext,
+    link: &PostLink,
+    file: &Utf8Path,
+    max_size: Option<u64>,
+    sniff: bool,
+    bytes: &ProgressBar,
+) -> Result<DownloadOutcome> {
+    use tokio::process::Command;
+
+    let directory = file.parent().unwrap();
+    tokio::fs::create_dir_all(directory).await?;
+
+    // aria2c has no size limit of its own, so ask the server up front
+    if let Some(max_size) = max_size {
+        if let Some(size) = fetch_size(context, link).await? {
+            if size > max_size {
+                info!("skipped {} because it is {} bytes large", link.url, size);
+                return Ok(DownloadOutcome::SkippedTooLarge);
+            }
+        }
+    }
+
+    let url = format!("{BASE_URL}{}", link.url);
+    let part_file = partial_path(file);
+    let args = aria2c_args(
+        &url,
+        directory,
+        part_file.file_name().unwrap(),
+        &context.configuration.cookie,
+        context.configuration.proxy.as_deref(),
+    );
+    let output = Command::new("aria2c")
+        .args(&args)
+        .kill_on_drop(true)
+        .output()
+        .await?;
+    let log = String::from_utf8_lossy(&output.stdout);
+    for line in log.lines().filter(|line| !line.trim().is_empty()) {
+        debug!("aria2c: {}", line);
+    }
+    if !output.status.success() {
+        const MAX_ERROR_LINES: usize = 5;
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        let lines: Vec<_> = log
+            .lines()
+            .chain(stderr.lines())
+            .filter(|line| !line.trim().is_empty())
+            .collect();
+        let start = lines.len().saturating_sub(MAX_ERROR_LINES);
+        bail!(
+            "failed to download {} with aria2c, exit code {}: {}",
+            link.url,
+            output.status,
+            lines[start..].join("\n")
+        );
+    }
+
+    let path = finish_download(context, link, &part_file, file, sniff).await?;
+    let size = tokio::fs::metadata(&path).await?.len();
+    bytes.inc(size);
+    info!("downloaded {} with aria2c to {}", url, path);
+
+    Ok(DownloadOutcome::Downloaded {
+        path,
+        cache_headers: CacheHeaders::default(),
+    })
+}
+
+async fn is_file(path: &Utf8Path) -> bool {
+    tokio::fs::metadata(path)
+        .await
+        .map(|m| m.is_file())
+        .unwrap_or(false)
+}
+
+/// Where an already downloaded file for the link is: the path the current pattern produces, or
+/// the path stored for it if the file ended up somewhere else (e.g. with another extension).
+async fn existing_path(filename: &Utf8Path, link: &PostLink) -> Option<Utf8PathBuf> {
+    if is_file(filename).await {
+        return Some(filename.to_owned());
+    }
+    match &link.file_path {
+        Some(path) if is_file(Utf8Path::new(path)).await => Some(path.into()),
+        _ => None,
+    }
+}
+
+/// Asks the server how large the file of a link is without downloading it. `None` if the server
+/// doesn't say.
+async fn fetch_size(context: &DownloadContext, link: &PostLink) -> Result<Option<u64>> {
+    let response = context
+        .client
+        .head(format!("{BASE_URL}{}", link.url))
+        .header("Cookie", &context.configuration.cookie)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()?;
+    // `Response::content_length` is the length of the (empty) body for HEAD requests
+    let size = response
+        .headers()
+        .get(CONTENT_LENGTH)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.trim().parse().ok());
+    Ok(size.filter(|&size| size > 0))
+}
+
+/// The sizes of the files behind `links` by link id. Sizes stored by an earlier run are reused,
+/// the others are requested from the server and stored. Links of unknown size are left out.
+async fn expected_sizes(
+    context: &DownloadContext,
+    links: Vec<&PostLink>,
+    concurrency: usize,
+) -> HashMap<i64, u64> {
+    stream::iter(links)
+        .map(|link| async move {
+            if let Some(size) = link.expected_size {
+                return Some((link.id, size as u64));
+            }
+            match fetch_size(context, link).await {
+                Ok(Some(size)) => {
+                    if let Err(e) = context
+                        .database
+                        .set_expected_size(link.id, size as i64)
+                        .await
+                    {
+                        warn!("failed to store the size of link {}: {e}", link.id);
+                    }
+                    Some((link.id, size))
+                }
+                Ok(None) => {
+                    debug!("no size reported for {}", link.url);
+                    None
+                }
+                Err(e) => {
+                    warn!("failed to get the size of {}: {e}", link.url);
+                    None
+                }
+            }
         })
-        .collect();
+        .buffer_unordered(concurrency.max(1))
+        .filter_map(future::ready)
+        .collect()
+        .await
+}
 
-    let db = &context.database;
-    let progress = if args.progress {
-        ProgressBar::new(posts.iter().map(|post| post.links.len()).sum::<usize>() as u64)
+/// A link that needs to be processed, with the paths that were resolved for it up front.
+struct LinkJob<'a> {
+    post: &'a Post,
+    link: &'a PostLink,
+    filename: Utf8PathBuf,
+    existing: Option<Utf8PathBuf>,
+}
+
+/// Longest error message stored for a link, so a huge response body can't bloat the database.
+const MAX_ERROR_LENGTH: usize = 1000;
+
+/// The error with all of its causes, e.g. `error sending request: ...: dns error: ...`, cut off
+/// after [`MAX_ERROR_LENGTH`] characters.
+fn error_chain(error: &color_eyre::Report) -> String {
+    let chain: Vec<_> = error.chain().map(|cause| cause.to_string()).collect();
+    let message = chain.join(": ");
+    if message.chars().count() > MAX_ERROR_LENGTH {
+        let mut message: String = message.chars().take(MAX_ERROR_LENGTH - 3).collect();
+        message.push_str("...");
+        message
     } else {
-        ProgressBar::hidden()
+        message
+    }
+}
+
+async fn process_link(
+    context: &DownloadContext,
+    args: &DownloadArgs,
+    job: LinkJob<'_>,
+    progress: &Progress,
+) -> Result<LinkResult> {
+    let LinkJob {
+        post,
+        link,
+        filename,
+        existing,
+    } = job;
+    let db = &context.database;
+    let pattern = args.pattern(post.post_type);
+    let mut result = LinkResult {
+        post_id: post.id,
+        link_id: link.id,
+        status: "downloaded",
+        path: None,
+        bytes: None,
+        error: None,
+        report: None,
     };
 
-    let style = ProgressStyle::with_template(
-        "[{elapsed_precise}] {bar:40.cyan/blue} {pos:>7}/{len:7} {msg}",
+    progress
+        .links
+        .set_message(format!("Downloading {filename}"));
+    info!("Downloading link {}/{} to {}", post.id, link.id, filename);
+    // images downloaded with validators can be checked for changes with a conditional request
+    let conditional = args.check_updates
+        && post.post_type == PostType::Image
+        && (link.etag.is_some() || link.last_modified.is_some());
+    // only the final path counts, in-progress downloads live in a `.part` file next to it
+    if let Some(existing) = existing.filter(|_| !conditional) {
+        info!(
+            "File {} already exists, skipping and updating state in database",
+            existing
+        );
+        db.update_status(
+            link.id,
+            StatusUpdate::Success {
+                file_path: existing.to_string(),
+                file_path_pattern: pattern.to_string(),
+                etag: None,
+                last_modified: None,
+            },
+        )
+        .await?;
+        result.status = "existing";
+        result.path = Some(existing);
+        return Ok(result);
+    }
+    if args.dry_run {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        debug!("Dry run: not updating status for post {}", post.id);
+        result.status = "dry_run";
+        result.path = Some(filename);
+        return Ok(result);
+    }
+
+    // conditional requests for `--check-updates` need the built-in client
+    let use_aria2c = context.configuration.downloader == Some(Downloader::Aria2c) && !conditional;
+    let outcome = match post.post_type {
+        PostType::Video => {
+            let referer = context.configuration.referer(&post.creator);
+            download_video(
+                context,
+                link,
+                &filename,
+                args.max_size,
+                &referer,
+                &progress.links,
+            )
+            .await
+        }
+        PostType::Image if use_aria2c => {
+            download_with_aria2c(
+                context,
+                link,
+                &filename,
+                args.max_size,
+                args.sniff_content_type,
+                &progress.bytes,
+            )
+            .await
+        }
+        PostType::Image => {
+            download_images(
+                context,
+                link,
+                &filename,
+                args.max_size,
+                conditional,
+                args.sniff_content_type,
+                &progress.bytes,
+            )
+            .await
+        }
+    };
+
+    match outcome {
+        Ok(DownloadOutcome::SkippedTooLarge) => {
+            db.update_status(link.id, StatusUpdate::SkippedTooLarge)
+                .await?;
+            result.status = "skipped_too_large";
+        }
+        Ok(DownloadOutcome::AlreadyArchived) => {
+            // trust yt-dlp's archive, the file may have been moved since
+            let file_path = link
+                .file_path
+                .clone()
+                .unwrap_or_else(|| filename.to_string());
+            db.update_status(
+                link.id,
+                StatusUpdate::Success {
+                    file_path,
+                    file_path_pattern: pattern.to_string(),
+                    etag: None,
+                    last_modified: None,
+                },
+            )
+            .await?;
+            result.status = "archived";
+        }
+        Ok(DownloadOutcome::NotModified) => {
+            db.update_status(
+                link.id,
+                StatusUpdate::Success {
+                    file_path: filename.to_string(),
+                    file_path_pattern: pattern.to_string(),
+                    etag: None,
+                    last_modified: None,
+                },
+            )
+            .await?;
+            result.status = "not_modified";
+            result.path = Some(filename);
+        }
+        Ok(DownloadOutcome::Downloaded {
+            path,
+            cache_headers,
+        }) => {
+            db.update_status(
+                link.id,
+                StatusUpdate::Success {
+                    file_path: path.to_string(),
+                    file_path_pattern: pattern.to_string(),
+                    etag: cache_headers.etag,
+                    last_modified: cache_headers.last_modified,
+                },
+            )
+            .await?;
+            let bytes = tokio::fs::metadata(&path)
+                .await
+                .map(|m| m.len())
+                .unwrap_or_default();
+            // images are counted chunk by chunk while they download
+            if post.post_type == PostType::Video {
+                progress.bytes.inc(bytes);
+            }
+
+            if let Some(hook) = &context.configuration.post_download_hook {
+                if let Err(e) = run_post_download_hook(hook, &path, post.id, &link.url).await {
+                    if context.configuration.hook_fail_fast.unwrap_or(false) {
+                        return Err(e);
+                    }
+                    warn!("{e}");
+                }
+            }
+            result.path = Some(path);
+            result.bytes = Some(bytes);
+        }
+        Err(e) => {
+            let error = error_chain(&e);
+            db.update_status(
+                link.id,
+                StatusUpdate::Error {
+                    error: error.clone(),
+                },
+            )
+            .await?;
+            result.status = "error";
+            result.error = Some(error);
+            result.report = Some(e);
+        }
+    }
+
+    Ok(result)
+}
+
+fn quota_message(downloaded: u64, quota: u64) -> String {
+    format!(
+        "({} of the {} quota)",
+        HumanBytes(downloaded),
+        HumanBytes(quota)
     )
-    .unwrap();
-    progress.set_style(style);
+}
+
+pub async fn run(context: DownloadContext, args: DownloadArgs) -> Result<DownloadStats> {
+    let started_at = Utc::now();
+    let started = Instant::now();
+    let deadline = args.deadline.map(|deadline| Instant::now() + deadline);
+    let past_deadline = || deadline.is_some_and(|deadline| Instant::now() >= deadline);
+    let wanted = |link: &PostLink| !args.retry_errors || link.status == LinkStatus::Error;
+    let has_work = |post: &Post| {
+        if args.retry_errors {
+            return post.links.iter().any(wanted);
+        }
+        args.check_updates
+            || post
+                .links
+                .iter()
+                .any(|link| link.status != LinkStatus::Downloaded)
+    };
+    // only the posts with something to do are kept in memory, not the whole archive
+    let mut posts = vec![];
+    let mut stream = context.database.stream_all();
+    while let Some(post) = stream.next_post().await? {
+        if has_work(&post) && args.title_filter.matches(&post.title) {
+            posts.push(post);
+        }
+    }
+    // releases the connection before the downloads start writing
+    drop(stream);
+    if let Some(order) = args.order {
+        // `None` sorts before any date, so undated posts come first with `oldest` and last with
+        // `newest`, ordered by id among themselves
+        posts.sort_by_key(|post| (post.created_at, post.id));
+        if let PostOrder::Newest = order {
+            posts.reverse();
+        }
+    }
+    if args.select {
+        posts = select_posts(posts)?;
+    }
 
-    for post in posts.iter() {
+    let link_count = posts
+        .iter()
+        .map(|post| post.links.iter().filter(|link| wanted(link)).count())
+        .sum::<usize>();
+
+    let extensions = context.configuration.extension_map();
+    let mut image_jobs = vec![];
+    let mut video_jobs = vec![];
+    for post in &posts {
         info!("post {}: type {:?}", post.id, post.post_type);
 
-        for link in &post.links {
-            let pattern = &args.filename_pattern[&post.post_type];
-            let filename = get_download_path(post, link.id, pattern, &args.path);
-            progress.set_message(format!("Downloading {filename}"));
-            info!("Downloading link {}/{} to {}", post.id, link.id, filename);
-            if filename.is_file() {
-                info!(
-                    "File {} already exists, skipping and updating state in database",
-                    filename
-                );
-                db.update_status(
+        let pattern = args.pattern(post.post_type);
+        let base_dir = bucket_directory(&args.path, post, args.bucket_by);
+        let filenames: Vec<_> = post
+            .links
+            .iter()
+            .map(|link| {
+                get_download_path(
+                    post,
                     link.id,
-                    StatusUpdate::Success {
-                        file_path: filename.to_string(),
-                        file_path_pattern: pattern.to_string(),
-                    },
+                    link.original_name.as_deref(),
+                    &link.content_type,
+                    &extensions,
+                    pattern,
+                    &base_dir,
                 )
-                .await?;
-                progress.inc(1);
+            })
+            .collect();
+        // stat all files of the post at once instead of blocking the runtime per link
+        let existing = join_all(
+            post.links
+                .iter()
+                .zip(&filenames)
+                .map(|(link, filename)| existing_path(filename, link)),
+        )
+        .await;
+
+        let jobs = match post.post_type {
+            PostType::Image => &mut image_jobs,
+            PostType::Video => &mut video_jobs,
+        };
+        for ((link, filename), existing) in post.links.iter().zip(filenames).zip(existing) {
+            if !wanted(link) {
                 continue;
             }
-            if !args.dry_run {
-                let result = match post.post_type {
-                    PostType::Video => download_video(&context, &link, &filename).await,
-                    PostType::Image => download_images(&context, &link, &filename).await,
-                };
-
-                match result {
-                    Ok(_) => {
-                        db.update_status(
-                            link.id,
-                            StatusUpdate::Success {
-                                file_path: filename.to_string(),
-                                file_path_pattern: pattern.to_string(),
-                            },
-                        )
-                        .await?
-                    }
-                    Err(e) => {
-                        db.update_status(
-                            link.id,
-                            StatusUpdate::Error {
-                                error: e.to_string(),
-                            },
-                        )
-                        .await?;
-
-                        if args.fail_fast {
-                            return Err(e);
-                        }
-                    }
-                }
-            } else {
-                tokio::time::sleep(Duration::from_millis(100)).await;
-                debug!("Dry run: not updating status for post {}", post.id);
+            jobs.push(LinkJob {
+                post,
+                link,
+                filename,
+                existing,
+            });
+        }
+    }
+
+    let downloader = context.configuration.downloader.unwrap_or_default();
+    if downloader == Downloader::Aria2c && !args.dry_run && !image_jobs.is_empty() {
+        check_binary("aria2c").await?;
+    }
+
+    // only files that still have to be downloaded count towards the total
+    let sizes = if args.accurate_progress && !args.dry_run {
+        let links: Vec<_> = image_jobs
+            .iter()
+            .chain(&video_jobs)
+            .filter(|job| job.existing.is_none())
+            .map(|job| job.link)
+            .collect();
+        info!("Checking the size of {} files", links.len());
+        let sizes = expected_sizes(&context, links, args.image_concurrency).await;
+        Some(sizes)
+    } else {
+        None
+    };
+    let total_bytes = sizes.as_ref().map(|sizes| sizes.values().sum());
+    let progress = Progress::new(args.progress, link_count as u64, total_bytes);
+    if let Some(quota) = args.quota {
+        progress.bytes.set_message(quota_message(0, quota));
+    }
+
+    // images and videos run in separate pools, yt-dlp is a lot heavier than a single request.
+    // After the deadline or with the quota used up no new downloads are started, but the running
+    // ones are finished.
+    let downloaded_bytes = AtomicU64::new(0);
+    let quota_used_up = || {
+        args.quota
+            .is_some_and(|quota| downloaded_bytes.load(Ordering::Relaxed) >= quota)
+    };
+    let can_start = || future::ready(!past_deadline() && !quota_used_up());
+    let images = stream::iter(image_jobs)
+        .take_while(|_| can_start())
+        .map(|job| process_link(&context, &args, job, &progress))
+        .buffer_unordered(args.image_concurrency.max(1));
+    let videos = stream::iter(video_jobs)
+        .take_while(|_| can_start())
+        .map(|job| process_link(&context, &args, job, &progress))
+        .buffer_unordered(args.video_concurrency.max(1));
+    let mut results = pin!(stream::select(images, videos));
+
+    let mut stats = DownloadStats::default();
+    while let Some(result) = results.next().await {
+        let mut result = result?;
+        result.print(args.json)?;
+        if result.status == "downloaded" {
+            stats.downloaded += 1;
+        }
+        let bytes = result.bytes.unwrap_or_default();
+        let downloaded = downloaded_bytes.fetch_add(bytes, Ordering::Relaxed) + bytes;
+        if let Some(quota) = args.quota {
+            progress.bytes.set_message(quota_message(downloaded, quota));
+        }
+        progress.links.inc(1);
+        if let Some(sizes) = &sizes {
+            // swap the expected size for the actual one, so skipped and failed files and files
+            // of unknown size don't throw the total off
+            let expected = sizes.get(&result.link_id).copied().unwrap_or_default();
+            let length = progress.bytes.length().unwrap_or_default();
+            progress
+                .bytes
+                .set_length((length + result.bytes.unwrap_or_default()).saturating_sub(expected));
+        }
+        stats.processed += 1;
+
+        if let Some(report) = result.report.take() {
+            stats.errors += 1;
+            if args.fail_fast {
+                return Err(report);
             }
-            progress.inc(1);
         }
     }
+    let downloaded_bytes = downloaded_bytes.load(Ordering::Relaxed);
 
-    Ok(())
+    if !args.dry_run {
+        let run = RunStats::new("download", started_at, started.elapsed(), stats.processed);
+        // stdout only has the JSON lines in `--json` mode
+        if !args.json {
+            println!(
+                "Processed {} ({} downloaded)",
+                run.describe(),
+                HumanBytes(downloaded_bytes)
+            );
+        }
+        if let Err(e) = context.database.insert_run(&run).await {
+            warn!("failed to store the run statistics: {e}");
+        }
+    }
+    let stopped_early = stats.processed < link_count;
+    if stopped_early && past_deadline() {
+        // stderr, so the JSON lines of `--json` stay parseable
+        eprintln!(
+            "Reached the deadline after {} of {link_count} links ({} downloaded), the rest stays pending",
+            stats.processed,
+            HumanBytes(downloaded_bytes)
+        );
+    }
+    if let Some(quota) = args.quota.filter(|_| stopped_early && quota_used_up()) {
+        eprintln!(
+            "Reached the download quota of {} after {} of {link_count} links ({} downloaded), the rest stays pending",
+            HumanBytes(quota),
+            stats.processed,
+            HumanBytes(downloaded_bytes)
+        );
+    }
+
+    Ok(stats)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino::Utf8Path;
+    use color_eyre::eyre::eyre;
+
+    use super::{
+        aria2c_args, error_chain, has_extension, parse_duration, parse_progress, parse_regex,
+        parse_size, post_label, TitleFilter,
+    };
+    use crate::database::{Post, PostType};
+
+    #[test]
+    fn test_parse_duration() {
+        assert_eq!(parse_duration("90s"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_duration("45m"), Ok(Duration::from_secs(45 * 60)));
+        assert_eq!(parse_duration("1h30m"), Ok(Duration::from_secs(90 * 60)));
+        assert_eq!(parse_duration("2d"), Ok(Duration::from_secs(2 * 86400)));
+        assert!(parse_duration("30").is_err());
+        assert!(parse_duration("5y").is_err());
+        assert!(parse_duration("").is_err());
+    }
+
+    #[test]
+    fn test_parse_size() {
+        assert_eq!(parse_size("1024"), Ok(1024));
+        assert_eq!(parse_size("500M"), Ok(500 * 1024 * 1024));
+        assert_eq!(parse_size("1.5G"), Ok(3 * 512 * 1024 * 1024));
+        assert_eq!(parse_size("10kb"), Ok(10 * 1024));
+        assert!(parse_size("10X").is_err());
+        assert!(parse_size("M").is_err());
+    }
+
+    #[test]
+    fn test_post_label() {
+        let mut post = Post {
+            id: 7,
+            title: "A   very\nlong title".into(),
+            creator: "creator".into(),
+            tags: vec!["beach".into(), "summer".into()],
+            post_type: PostType::Image,
+            like_count: 0,
+            links: vec![],
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+        assert_eq!(
+            post_label(&post),
+            "7 [Image] A very long title (#beach #summer)"
+        );
+        post.tags.clear();
+        assert_eq!(post_label(&post), "7 [Image] A very long title");
+    }
+
+    #[test]
+    fn test_aria2c_args() {
+        let args = aria2c_args(
+            "https://hutt.co/images/1/big",
+            Utf8Path::new("downloads/Images"),
+            "1.jpeg.part",
+            "session=abc",
+            Some("http://localhost:8080"),
+        );
+        let position = |arg: &str| args.iter().position(|a| a == arg).unwrap();
+        assert_eq!(args[position("--dir") + 1], "downloads/Images");
+        assert_eq!(args[position("--out") + 1], "1.jpeg.part");
+        assert!(args.contains(&"Cookie: session=abc".to_string()));
+        assert!(args.contains(&"--all-proxy=http://localhost:8080".to_string()));
+        assert_eq!(args.last().unwrap(), "https://hutt.co/images/1/big");
+    }
+
+    #[test]
+    fn test_has_extension() {
+        assert!(has_extension(Utf8Path::new("a/1.jpeg"), "jpg"));
+        assert!(has_extension(Utf8Path::new("a/1.JPG"), "jpeg"));
+        assert!(!has_extension(Utf8Path::new("a/1.jpeg"), "png"));
+        assert!(!has_extension(Utf8Path::new("a/1"), "png"));
+    }
+
+    #[test]
+    fn test_title_filter() {
+        let filter = TitleFilter::new(Some("photoset"), Some("reminder"), false).unwrap();
+        assert!(filter.matches("New Photoset!"));
+        assert!(!filter.matches("Photoset reminder"));
+        assert!(!filter.matches("Just a video"));
+
+        let filter = TitleFilter::new(Some("photoset"), None, true).unwrap();
+        assert!(!filter.matches("New Photoset!"));
+        assert!(filter.matches("new photoset"));
+
+        assert!(TitleFilter::default().matches("anything"));
+        assert!(parse_regex("(unclosed").is_err());
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress("[download]  42.3% of ~ 10.00MiB at  1.00MiB/s ETA 00:05"),
+            Some(42.3)
+        );
+        assert_eq!(
+            parse_progress("[download] 100% of 10.00MiB in 00:00:10"),
+            Some(100.0)
+        );
+        assert_eq!(
+            parse_progress("[download] Destination: 123 - title.mp4.part"),
+            None
+        );
+        assert_eq!(parse_progress("[info] Downloading 1 format(s): 0"), None);
+    }
+
+    #[test]
+    fn test_error_chain() {
+        let error = eyre!("dns error: no such host").wrap_err("error sending request");
+        assert_eq!(
+            error_chain(&error),
+            "error sending request: dns error: no such host"
+        );
+
+        let error = eyre!("x".repeat(2000));
+        let message = error_chain(&error);
+        assert_eq!(message.chars().count(), super::MAX_ERROR_LENGTH);
+        assert!(message.ends_with("..."));
+    }
 }
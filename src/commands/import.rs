@@ -0,0 +1,72 @@
+use camino::Utf8PathBuf;
+use sqlx::SqlitePool;
+use tracing::info;
+
+use crate::database::{CreatePost, Database, LinkStatus, StatusUpdate};
+use crate::{DownloadContext, Result};
+
+pub struct ImportArgs {
+    pub other: Utf8PathBuf,
+}
+
+pub async fn run(context: DownloadContext, args: ImportArgs) -> Result<()> {
+    let pool = SqlitePool::connect(&format!("sqlite:{}?mode=ro", args.other)).await?;
+    let other = Database::new(pool);
+    let posts = other.fetch_all().await?;
+    let database = &context.database;
+
+    let mut posts_added = 0;
+    let mut links_added = 0;
+    let mut links_updated = 0;
+    for post in &posts {
+        let (post_added, new_links) = database.insert_post_if_new(&CreatePost::from(post)).await?;
+        links_added += new_links;
+        if post_added {
+            info!("imported post {}", post.id);
+            posts_added += 1;
+            if let Some(date) = post.created_at {
+                database.set_post_date(post.id, date).await?;
+            }
+            if let Some(title) = &post.generated_title {
+                database.set_generated_title(post.id, title).await?;
+            }
+        }
+
+        // prefer the downloaded state of a link if either database has it
+        let Some(existing) = database.fetch_by_id(post.id).await? else {
+            continue;
+        };
+        for link in &post.links {
+            if link.status != LinkStatus::Downloaded {
+                continue;
+            }
+            let existing_link = existing
+                .links
+                .iter()
+                .find(|l| l.url == link.url && l.status != LinkStatus::Downloaded);
+            if let (Some(existing_link), Some(file_path)) = (existing_link, &link.file_path) {
+                info!("marking link {} as downloaded", existing_link.id);
+                database
+                    .update_status(
+                        existing_link.id,
+                        StatusUpdate::Success {
+                            file_path: file_path.clone(),
+                            file_path_pattern: link.file_path_pattern.clone().unwrap_or_default(),
+                            etag: link.etag.clone(),
+                            last_modified: link.last_modified.clone(),
+                        },
+                    )
+                    .await?;
+                links_updated += 1;
+            }
+        }
+    }
+
+    println!(
+        "Imported {posts_added} posts and {links_added} links from {}",
+        args.other
+    );
+    println!("Marked {links_updated} existing links as downloaded");
+
+    Ok(())
+}
@@ -0,0 +1,120 @@
+use std::io::{IsTerminal, Write};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::bail;
+use reqwest::Client;
+
+use crate::commands::resolve_creator::{self, ResolvedCreator};
+use crate::{database, Configuration, Result};
+
+pub struct InitArgs {
+    pub config_path: Utf8PathBuf,
+    pub database_path: Utf8PathBuf,
+    pub interactive: bool,
+}
+
+fn prompt(question: &str) -> Result<String> {
+    print!("{question} ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    Ok(answer.trim().to_string())
+}
+
+/// The configuration template with the required fields filled in.
+fn fill_template(cookie: &str, creator: &ResolvedCreator) -> Result<String> {
+    let template = Configuration::TEMPLATE
+        .replacen(
+            r#"creatorName: "<NAME HERE>""#,
+            &format!("creatorName: {}", serde_json::to_string(&creator.name)?),
+            1,
+        )
+        .replacen(
+            "creatorId: 123456",
+            &format!("creatorId: {}", creator.id),
+            1,
+        )
+        .replacen(
+            r#"cookie: "<ENTER COOKIE HERE>""#,
+            &format!("cookie: {}", serde_json::to_string(cookie)?),
+            1,
+        );
+    Ok(template)
+}
+
+fn print_instructions(config_path: &Utf8Path) {
+    println!("Created default configuration file at `{config_path}`.");
+    println!("Short instructions:");
+    println!();
+
+    println!("1. Log in to Hutt in your browser.");
+    println!("2. Open the developer tools (F12) and go to the Network tab.");
+    println!("3. Refresh the page.");
+    println!("4. Find the request to any of the API endpoints and copy the `Cookie` header.");
+    println!("5. Paste the `Cookie` header into the `cookie` field in the configuration file.");
+    println!("6. Run `hutt-archiver resolve-creator <profile url>` to look up the creator's ID and name.");
+    println!("7. Set the `creatorId` and `creatorName` fields to the values it printed.");
+}
+
+async fn create_config(args: &InitArgs) -> Result<()> {
+    if let Some(existing) = Configuration::find(&args.config_path) {
+        println!("Configuration file `{existing}` already exists, leaving it as is.");
+        return Ok(());
+    }
+
+    if !args.interactive {
+        std::fs::write(&args.config_path, Configuration::TEMPLATE)?;
+        print_instructions(&args.config_path);
+        return Ok(());
+    }
+
+    if !std::io::stdin().is_terminal() {
+        bail!("--interactive needs a terminal to ask questions");
+    }
+    println!(
+        "Log in to Hutt in your browser, open the developer tools (F12), refresh the page and"
+    );
+    println!("copy the `Cookie` header of any request in the Network tab.");
+    let cookie = prompt("Cookie:")?;
+    let url = prompt("Creator profile URL (e.g. https://hutt.co/someone):")?;
+    let creator = resolve_creator::resolve(&Client::new(), &cookie, &url).await?;
+
+    std::fs::write(&args.config_path, fill_template(&cookie, &creator)?)?;
+    println!(
+        "Created configuration file at `{}` for creator {} ({}).",
+        args.config_path, creator.name, creator.id
+    );
+
+    Ok(())
+}
+
+pub async fn run(args: InitArgs) -> Result<()> {
+    create_config(&args).await?;
+
+    let pool = database::create(&args.database_path).await?;
+    pool.close().await;
+    println!("Database `{}` is up to date.", args.database_path);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fill_template;
+    use crate::commands::resolve_creator::ResolvedCreator;
+    use crate::Configuration;
+
+    #[test]
+    fn test_fill_template() {
+        let creator = ResolvedCreator {
+            id: 4242,
+            name: "some \"one\"".into(),
+        };
+        let content = fill_template("session=abc", &creator).unwrap();
+        let config: Configuration = json5::from_str(&content).unwrap();
+
+        assert_eq!(config.creator_id, 4242);
+        assert_eq!(config.creator_name, "some \"one\"");
+        assert_eq!(config.cookie, "session=abc");
+    }
+}
@@ -0,0 +1,164 @@
+use std::io::Write;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::bail;
+use sha2::{Digest, Sha256};
+use tracing::{info, warn};
+
+use crate::database::LinkStatus;
+use crate::{DownloadContext, Result};
+
+pub struct ManifestArgs {
+    pub output: Utf8PathBuf,
+    pub verify: bool,
+}
+
+/// Hashes a file without loading it into memory at once.
+async fn hash_file(path: Utf8PathBuf) -> Result<String> {
+    tokio::task::spawn_blocking(move || -> Result<String> {
+        let mut file = std::fs::File::open(&path)?;
+        let mut hasher = Sha256::new();
+        std::io::copy(&mut file, &mut hasher)?;
+        Ok(format!("{:x}", hasher.finalize()))
+    })
+    .await?
+}
+
+/// The path of a downloaded file relative to the download directory, as written to the manifest.
+/// Both are resolved first, since stored paths are usually relative to where the download ran
+/// while `--output-dir` and `--data-dir` make the download directory absolute.
+fn relative_path(path: &Utf8Path, base: &Utf8Path) -> Utf8PathBuf {
+    let resolve = |path: &Utf8Path| path.canonicalize_utf8().unwrap_or_else(|_| path.to_owned());
+    match resolve(path).strip_prefix(resolve(base)) {
+        Ok(relative) => relative.to_owned(),
+        Err(_) => path.to_owned(),
+    }
+}
+
+/// Parses a `sha256sum` line, `<hash>  <path>` or `<hash> *<path>` in binary mode.
+fn parse_line(line: &str) -> Option<(&str, &str)> {
+    let (hash, rest) = line.split_once(' ')?;
+    let path = rest.strip_prefix([' ', '*'])?;
+    let valid = hash.len() == 64 && hash.chars().all(|c| c.is_ascii_hexdigit());
+    (valid && !path.is_empty()).then_some((hash, path))
+}
+
+async fn write_manifest(context: &DownloadContext, output: &Utf8Path) -> Result<()> {
+    let base = context.configuration.download_directory();
+    let mut posts = context.database.stream_all();
+    let mut manifest = std::io::BufWriter::new(std::fs::File::create(output)?);
+    let mut count = 0;
+
+    while let Some(post) = posts.next_post().await? {
+        for link in &post.links {
+            if link.status != LinkStatus::Downloaded {
+                continue;
+            }
+            let Some(path) = &link.file_path else {
+                continue;
+            };
+            let path = Utf8Path::new(path);
+            match hash_file(path.to_owned()).await {
+                Ok(hash) => {
+                    writeln!(manifest, "{hash}  {}", relative_path(path, base))?;
+                    count += 1;
+                }
+                Err(e) => warn!("failed to hash {path}: {e}"),
+            }
+        }
+    }
+    manifest.flush()?;
+
+    println!("Wrote checksums of {count} files to {output}");
+    println!("Check them with `sha256sum -c` from inside {base}.");
+    Ok(())
+}
+
+async fn verify_manifest(context: &DownloadContext, manifest: &Utf8Path) -> Result<()> {
+    let base = context.configuration.download_directory();
+    let content = tokio::fs::read_to_string(manifest).await?;
+    let mut checked = 0;
+    let mut failed = 0;
+
+    for line in content.lines().filter(|line| !line.trim().is_empty()) {
+        let Some((expected, path)) = parse_line(line) else {
+            warn!("ignoring malformed manifest line `{line}`");
+            continue;
+        };
+        checked += 1;
+        let path = base.join(path);
+        match hash_file(path.clone()).await {
+            Ok(hash) if hash.eq_ignore_ascii_case(expected) => info!("{path}: OK"),
+            Ok(_) => {
+                println!("{path}: checksum mismatch");
+                failed += 1;
+            }
+            Err(e) => {
+                println!("{path}: {e}");
+                failed += 1;
+            }
+        }
+    }
+
+    if failed > 0 {
+        bail!("{failed} of {checked} files failed verification");
+    }
+    println!("All {checked} files match {manifest}");
+    Ok(())
+}
+
+pub async fn run(context: DownloadContext, args: ManifestArgs) -> Result<()> {
+    if args.verify {
+        verify_manifest(&context, &args.output).await
+    } else {
+        write_manifest(&context, &args.output).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use camino::{Utf8Path, Utf8PathBuf};
+
+    use super::{parse_line, relative_path};
+
+    const HASH: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn test_parse_line() {
+        let line = format!("{HASH}  Images/1 - title/2.jpeg");
+        assert_eq!(parse_line(&line), Some((HASH, "Images/1 - title/2.jpeg")));
+        let line = format!("{HASH} *Videos/3.mp4");
+        assert_eq!(parse_line(&line), Some((HASH, "Videos/3.mp4")));
+        assert_eq!(parse_line("abc  file.jpeg"), None);
+        assert_eq!(parse_line(&format!("{HASH}  ")), None);
+    }
+
+    #[test]
+    fn test_relative_path() {
+        let base = Utf8Path::new("./downloads");
+        assert_eq!(
+            relative_path(Utf8Path::new("./downloads/Images/1.jpeg"), base),
+            "Images/1.jpeg"
+        );
+        assert_eq!(
+            relative_path(Utf8Path::new("/elsewhere/1.jpeg"), base),
+            "/elsewhere/1.jpeg"
+        );
+    }
+
+    #[test]
+    fn test_relative_path_absolute_base() {
+        // stored paths are relative to the working directory
+        let directory = tempfile::tempdir_in(".").unwrap();
+        let name = directory.path().file_name().unwrap().to_str().unwrap();
+        std::fs::create_dir_all(directory.path().join("downloads/Images")).unwrap();
+        std::fs::write(directory.path().join("downloads/Images/1.jpeg"), "").unwrap();
+
+        let base = Utf8PathBuf::try_from(std::env::current_dir().unwrap())
+            .unwrap()
+            .join(name)
+            .join("downloads");
+        let path = format!("./{name}/downloads/Images/1.jpeg");
+        assert_eq!(relative_path(Utf8Path::new(&path), &base), "Images/1.jpeg");
+    }
+}
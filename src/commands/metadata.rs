@@ -1,282 +1,598 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::RangeInclusive;
+use std::time::{Duration, Instant};
+
+use camino::{Utf8Path, Utf8PathBuf};
+
+use chrono::{DateTime, Utc};
+use color_eyre::eyre::bail;
 use color_eyre::Result;
-use regex::Regex;
-use reqwest::StatusCode;
-use scraper::{ElementRef, Selector};
-use serde::Deserialize;
+use indicatif::{ProgressBar, ProgressStyle};
+use reqwest::header::RETRY_AFTER;
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
 use tracing::{info, warn};
 
-use crate::database::{CreatePost, CreatePostLink, LinkSource, PostType};
+use crate::database::{PostType, RunStats};
+use crate::scraping::{PostScraper, ScrapedPage, Selectors, SkipReason};
 use crate::DownloadContext;
 
 pub const USER_AGENT: &str = "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/124.0.0.0 Safari/537.36";
 
+/// Post counts of a metadata run.
+#[derive(Debug, Default)]
+pub struct MetadataStats {
+    pub scraped: usize,
+    pub new: usize,
+    pub skipped: usize,
+    /// Posts that were scraped but couldn't be stored.
+    pub failed: usize,
+}
+
 pub struct MetadataArgs {
     pub creator_name: String,
     pub creator_id: i64,
     pub cookie: String,
+    pub empty_page_tolerance: u32,
+    pub save_html: bool,
+    pub progress: bool,
+    pub batch_size: usize,
+    pub skip_type: Option<PostType>,
+    /// Stops at the first page whose posts are all in the database already.
+    pub only_new: bool,
+    /// Stops after this many pages, as a safety net against scraping forever.
+    pub max_pages: Option<u32>,
+    /// Doesn't fetch new pages once this much time has passed since the start of the run.
+    pub deadline: Option<Duration>,
+    /// Stores the comments of the posts as well.
+    pub with_comments: bool,
+    /// Updates posts that are already stored instead of leaving them as they were first scraped.
+    pub refetch_metadata: bool,
+    /// The page to start at, overriding the checkpoint.
+    pub start_page: Option<u32>,
+    /// Where the next page to scrape is saved after every page, if at all.
+    pub checkpoint: Option<Utf8PathBuf>,
+    /// Starts at the page saved in the checkpoint, if there is one for the creator.
+    pub resume: bool,
+    /// Scrapes the posts liked by the account of the cookie instead of the creator's own posts.
+    pub liked: bool,
 }
 
-#[derive(Deserialize)]
-struct GalleryImage {
-    src: Option<String>,
-    html: Option<String>,
+/// Where an interrupted `metadata` run stopped, so the next run can continue from there.
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Checkpoint {
+    creator_id: i64,
+    /// The first page that wasn't scraped completely.
+    page: u32,
 }
 
-struct UrlExtractor {}
-
-impl UrlExtractor {
-    fn parse_url_from_html(&self, html: &str) -> Option<CreatePostLink> {
-        let re = Regex::new(r#"src="(.*?)""#).unwrap();
-        if let Some(captures) = re.captures(html) {
-            let path = captures.get(1).unwrap().as_str().to_string();
-            return Some(CreatePostLink {
-                url: path,
-                content_type: "video/mp4".to_string(),
-                source: LinkSource::HtmlString,
-            });
-        } else {
-            None
-        }
-    }
-
-    fn extract_urls(&self, element: ElementRef, post_type: PostType) -> Vec<CreatePostLink> {
-        match post_type {
-            PostType::Image => {
-                let selector = Selector::parse("script").unwrap();
-                let script_el = element.select(&selector).next().unwrap().inner_html();
-                let re = Regex::new(r#"dynamicEl:\s+(.*),"#).unwrap();
-                if let Some(captures) = re.captures(&script_el) {
-                    let gallery_json = captures.get(1).unwrap().as_str().replace("\\>", " ");
-                    match serde_json::from_str::<Vec<GalleryImage>>(&gallery_json) {
-                        Ok(json) => {
-                            let mut post_links = Vec::new();
-                            for image in json {
-                                if let Some(src) = image.src {
-                                    post_links.push(CreatePostLink {
-                                        url: src,
-                                        content_type: "image/jpeg".to_string(),
-                                        source: LinkSource::ImageGallery,
-                                    });
-                                }
-                                if let Some(url) =
-                                    image.html.and_then(|html| self.parse_url_from_html(&html))
-                                {
-                                    post_links.push(url);
-                                }
-                            }
-                            return post_links;
-                        }
-                        Err(e) => {
-                            warn!("failed to parse gallery json: {gallery_json}: {e:?}");
-                            return Vec::new();
-                        }
-                    }
-                } else {
-                    warn!(
-                        "failed to find gallery json in script element {}",
-                        script_el
-                    );
-                    return Vec::new();
-                }
+impl Checkpoint {
+    /// The page saved for `creator_id`. Checkpoints of other creators and unreadable files are
+    /// ignored.
+    fn load(path: &Utf8Path, creator_id: i64) -> Option<u32> {
+        let content = std::fs::read_to_string(path).ok()?;
+        match serde_json::from_str::<Checkpoint>(&content) {
+            Ok(checkpoint) if checkpoint.creator_id == creator_id => Some(checkpoint.page),
+            Ok(checkpoint) => {
+                info!(
+                    "ignoring checkpoint {path} of creator {}",
+                    checkpoint.creator_id
+                );
+                None
             }
-            PostType::Video => {
-                let selector = Selector::parse("video source").unwrap();
-
-                if let Some(source_element) = element.select(&selector).next() {
-                    return vec![CreatePostLink {
-                        url: source_element.attr("src").unwrap().to_string(),
-                        content_type: "video/mp4".to_string(),
-                        source: LinkSource::VideoPost,
-                    }];
-                } else {
-                    warn!("failed to find video source element");
-                    return Vec::new();
-                }
+            Err(e) => {
+                warn!("ignoring unreadable checkpoint {path}: {e}");
+                None
             }
         }
     }
+
+    /// Writes the checkpoint next to its final path first, so an interrupted write doesn't
+    /// leave a broken file behind.
+    fn save(&self, path: &Utf8Path) -> Result<()> {
+        let temporary = Utf8PathBuf::from(format!("{path}.tmp"));
+        std::fs::write(&temporary, serde_json::to_string(self)?)?;
+        std::fs::rename(&temporary, path)?;
+        Ok(())
+    }
 }
 
-enum FetchResult {
-    RateLimited,
-    Posts(Vec<CreatePost>),
+/// How long to wait when rate limited and the server doesn't say.
+const DEFAULT_RETRY_AFTER: Duration = Duration::from_secs(120);
+
+/// Parses a `Retry-After` value, which is either a number of seconds or an HTTP date.
+fn parse_retry_after(value: &str, now: DateTime<Utc>) -> Option<Duration> {
+    let value = value.trim();
+    if let Ok(seconds) = value.parse::<u64>() {
+        return Some(Duration::from_secs(seconds));
+    }
+    let date = DateTime::parse_from_rfc2822(value).ok()?;
+    // dates in the past mean "retry now"
+    Some(
+        (date.with_timezone(&Utc) - now)
+            .to_std()
+            .unwrap_or_default(),
+    )
 }
 
-struct Selectors {
-    post_wrapper: Selector,
-    like_count: Selector,
-    title: Selector,
-    tags: Selector,
-    video_element: Selector,
-    image_element: Selector,
+/// How long to wait before retrying a rate-limited request, from its `Retry-After` header.
+pub fn retry_after(response: &Response) -> Duration {
+    response
+        .headers()
+        .get(RETRY_AFTER)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| parse_retry_after(value, Utc::now()))
+        .unwrap_or(DEFAULT_RETRY_AFTER)
 }
 
-impl Selectors {
-    fn new() -> Self {
-        Self {
-            post_wrapper: Selector::parse(".huttPost.has-media").unwrap(),
-            like_count: Selector::parse(".likes-count").unwrap(),
-            title: Selector::parse(".post-text").unwrap(),
-            tags: Selector::parse(".tags a.label").unwrap(),
-            video_element: Selector::parse("figure.hutt-video").unwrap(),
-            image_element: Selector::parse(".img-responsive").unwrap(),
-        }
-    }
+enum FetchResult {
+    RateLimited(Duration),
+    Posts(ScrapedPage),
 }
 
 struct PostFetcher {
     context: DownloadContext,
     args: MetadataArgs,
-    selectors: Selectors,
-    url_extractor: UrlExtractor,
+    scraper: PostScraper,
 }
 
 impl PostFetcher {
-    fn extract_post_type(&self, element: ElementRef) -> Option<PostType> {
-        let video = element.select(&self.selectors.video_element).next();
-        if video.is_some() {
-            return Some(PostType::Video);
+    async fn fetch_posts(&self, page: u32) -> Result<FetchResult> {
+        let creator_id = self.args.creator_id;
+        let creator_name = &self.args.creator_name;
+        let url = if self.args.liked {
+            info!("Fetching liked posts, page {page}");
+            format!("https://hutt.co/hutts/ajax-liked-posts?page={page}&view=view")
+        } else {
+            info!("Fetching posts for creator {creator_name} ({creator_id}), page {page}");
+            format!("https://hutt.co/hutts/ajax-posts?page={page}&view=view&id={creator_id}")
+        };
+        let response = self
+            .context
+            .client
+            .get(&url)
+            .header("Cookie", &self.args.cookie)
+            .header("User-Agent", USER_AGENT)
+            .send()
+            .await?;
+        if response.status() == StatusCode::TOO_MANY_REQUESTS {
+            Ok(FetchResult::RateLimited(retry_after(&response)))
+        } else {
+            let text = response.text().await?;
+            let scraped = self.scraper.scrape_posts(text, creator_name, Some(page));
+            Ok(FetchResult::Posts(scraped))
+        }
+    }
+
+    fn save_checkpoint(&self, page: u32) {
+        let Some(path) = &self.args.checkpoint else {
+            return;
+        };
+        let checkpoint = Checkpoint {
+            creator_id: self.args.creator_id,
+            page,
+        };
+        if let Err(e) = checkpoint.save(path) {
+            warn!("failed to save checkpoint {path}: {e}");
+        }
+    }
+
+    /// The page to start at: the one passed explicitly, the one from the checkpoint or the first.
+    fn start_page(&self) -> u32 {
+        if let Some(page) = self.args.start_page {
+            return page;
         }
-        let image = element.select(&self.selectors.image_element).next();
-        if image.is_some() {
-            return Some(PostType::Image);
+        let checkpoint = self
+            .args
+            .checkpoint
+            .as_deref()
+            .filter(|_| self.args.resume)
+            .and_then(|path| Checkpoint::load(path, self.args.creator_id).map(|page| (path, page)));
+        match checkpoint {
+            Some((path, page)) => {
+                println!("Resuming at page {page} from {path}, pass --full to start over");
+                page
+            }
+            None => 0,
         }
-        None
     }
 
-    fn extract_title(&self, element: ElementRef) -> String {
-        let text: Option<String> = element
-            .select(&self.selectors.title)
-            .next()
-            .map(|e| e.text().collect());
-        text.unwrap_or_else(|| "Untitled".into())
+    /// Counts a post that couldn't be stored as skipped and records it as a scrape error.
+    async fn insert_failed(
+        &self,
+        post_id: i64,
+        page: u32,
+        skipped: &mut BTreeMap<SkipReason, usize>,
+    ) -> Result<()> {
+        *skipped.entry(SkipReason::InsertFailed).or_default() += 1;
+        self.context
+            .database
+            .insert_scrape_error(
+                Some(&post_id.to_string()),
+                page.into(),
+                SkipReason::InsertFailed.description(),
+            )
+            .await
     }
 
-    fn extract_tags(&self, element: ElementRef) -> Vec<String> {
-        let elements = element.select(&self.selectors.tags);
-        let mut tags = vec![];
-        for tag_el in elements {
-            let tag: String = tag_el.text().collect();
-            let tag = tag.trim().to_string();
-            if !tag.is_empty() {
-                if tag.starts_with("#") {
-                    tags.push(tag[1..].to_string());
-                } else {
-                    tags.push(tag);
+    /// Fetches a page, waiting out rate limits until the configured number of retries is used up.
+    async fn fetch_page_with_retries(
+        &self,
+        page: u32,
+        progress: &ProgressBar,
+    ) -> Result<ScrapedPage> {
+        let max_retries = self.context.configuration.max_rate_limit_retries();
+        let mut rate_limited = 0;
+        loop {
+            match self.fetch_posts(page).await? {
+                FetchResult::RateLimited(_) if rate_limited >= max_retries => {
+                    bail!(
+                        "still rate limited on page {page} after {max_retries} retries, giving up"
+                    );
                 }
+                FetchResult::RateLimited(wait) => {
+                    rate_limited += 1;
+                    warn!("Rate limited, sleeping for {} seconds", wait.as_secs());
+                    progress.set_message(format!(
+                        "Page {page}: rate limited, sleeping for {} seconds",
+                        wait.as_secs()
+                    ));
+                    tokio::time::sleep(wait).await;
+                }
+                FetchResult::Posts(scraped) => return Ok(scraped),
             }
         }
-
-        tags
     }
 
-    fn scrape_posts(&self, text: String, creator_name: &str) -> Result<Vec<CreatePost>> {
-        let document = scraper::Html::parse_document(&text);
+    async fn run(&self) -> Result<MetadataStats> {
+        let start_page = self.start_page();
+        let mut page = start_page;
+        let mut empty_pages = 0;
+        let mut skipped: BTreeMap<SkipReason, usize> = BTreeMap::new();
+        let mut new_posts = 0;
+        let mut updated_posts = 0;
+        let mut scraped_posts = 0;
+        let started_at = Utc::now();
+        let started = Instant::now();
+        // pinned posts show up on every page, so they're only stored the first time
+        let mut seen_pinned = HashSet::new();
 
-        let mut posts = Vec::new();
+        let progress = if self.args.progress {
+            ProgressBar::new_spinner()
+        } else {
+            ProgressBar::hidden()
+        };
+        progress.set_style(
+            ProgressStyle::with_template("[{elapsed_precise}] {spinner} {msg}").unwrap(),
+        );
+        progress.enable_steady_tick(std::time::Duration::from_millis(100));
 
-        for element in document.select(&self.selectors.post_wrapper) {
-            if let Some(id) = element.attr("id") {
-                let id = id.replace("post-", "");
-                let id: i64 = id.parse()?;
-                info!("Scraping post {id}");
-                let post_type = self.extract_post_type(element);
-                if post_type.is_none() {
-                    warn!("No post type found for post {id}, skipping");
-                    continue;
+        let deadline = self.args.deadline.map(|deadline| Instant::now() + deadline);
+        let mut hit_page_cap = false;
+        let mut hit_deadline = false;
+        let mut new_comments = 0;
+        loop {
+            if self
+                .args
+                .max_pages
+                .is_some_and(|max_pages| page - start_page >= max_pages)
+            {
+                warn!("Reached the limit of {} pages, stopping", page - start_page);
+                hit_page_cap = true;
+                break;
+            }
+            if deadline.is_some_and(|deadline| Instant::now() >= deadline) {
+                info!("Reached the deadline before page {page}, stopping");
+                hit_deadline = true;
+                break;
+            }
+            let skipped_count: usize = skipped.values().sum();
+            // known posts count as found too, otherwise re-runs look stuck at zero
+            progress.set_message(format!(
+                "Page {page}: {scraped_posts} posts found, {new_posts} new, {skipped_count} skipped"
+            ));
+            let ScrapedPage {
+                posts,
+                skipped: skipped_posts,
+                comments,
+            } = match self.fetch_page_with_retries(page, &progress).await {
+                Ok(scraped) => scraped,
+                Err(e) => {
+                    progress.finish_and_clear();
+                    return Err(e);
                 }
-                let post_type = post_type.unwrap();
-                let links = self.url_extractor.extract_urls(element, post_type);
-                if links.is_empty() {
-                    info!("No links found for post {id}, skipping");
-                    continue;
+            };
+            scraped_posts += posts.len() + skipped_posts.len();
+            for skipped_post in skipped_posts {
+                *skipped.entry(skipped_post.reason).or_default() += 1;
+                self.context
+                    .database
+                    .insert_scrape_error(
+                        skipped_post.post_id.as_deref(),
+                        page.into(),
+                        skipped_post.reason.description(),
+                    )
+                    .await?;
+            }
+
+            let (pinned, posts): (Vec<_>, Vec<_>) = posts.into_iter().partition(|post| post.pinned);
+            let mut new_pinned = 0;
+            for post in pinned.iter().filter(|post| seen_pinned.insert(post.id)) {
+                new_pinned += 1;
+                let (added, _) = if self.args.refetch_metadata {
+                    self.context.database.upsert_post(post).await?
                 } else {
-                    info!("Found {} links for post {id}", links.len());
+                    self.context.database.insert_post_if_new(post).await?
+                };
+                if added {
+                    new_posts += 1;
+                } else if self.args.refetch_metadata {
+                    updated_posts += 1;
+                }
+            }
+
+            // a page with nothing but pinned posts that were seen before is empty
+            if posts.is_empty() && new_pinned == 0 {
+                if empty_pages >= self.args.empty_page_tolerance {
+                    info!("No more posts found, stopping");
+                    break;
+                }
+                empty_pages += 1;
+                warn!(
+                    "Page {page} was empty, trying the next page ({empty_pages}/{})",
+                    self.args.empty_page_tolerance
+                );
+                page += 1;
+                self.save_checkpoint(page);
+                continue;
+            }
+            empty_pages = 0;
+            if self.args.only_new && !posts.is_empty() {
+                let ids: Vec<_> = posts.iter().map(|post| post.id).collect();
+                let existing = self.context.database.existing_post_ids(&ids).await?;
+                if existing.len() == ids.len() {
+                    info!("All posts on page {page} are known already, stopping");
+                    break;
+                }
+            }
+            if self.args.refetch_metadata {
+                for post in &posts {
+                    match self.context.database.upsert_post(post).await {
+                        Ok((true, _)) => new_posts += 1,
+                        Ok((false, _)) => updated_posts += 1,
+                        Err(e) => {
+                            warn!("Failed to update post {}: {e}", post.id);
+                            self.insert_failed(post.id, page, &mut skipped).await?;
+                        }
+                    }
                 }
-                let title = self.extract_title(element);
-                let tags = self.extract_tags(element);
-                let like_count: Option<String> = element
-                    .select(&self.selectors.like_count)
-                    .next()
-                    .map(|e| e.text().collect());
-                let like_count: i64 = like_count.and_then(|s| s.parse().ok()).unwrap_or_default();
-
-                posts.push(CreatePost {
-                    id,
-                    like_count,
-                    post_type,
-                    tags: tags,
-                    links,
-                    title,
-                    creator: creator_name.to_string(),
-                })
             } else {
-                info!("No id found for post, skipping");
+                for batch in posts.chunks(self.args.batch_size.max(1)) {
+                    match self.context.database.insert_posts(batch).await {
+                        Ok(added) => new_posts += added,
+                        Err(e) => {
+                            warn!(
+                                "Failed to insert batch of {} posts ({e}), inserting them one by one",
+                                batch.len()
+                            );
+                            for post in batch {
+                                match self.context.database.insert_post_if_new(post).await {
+                                    Ok((true, _)) => new_posts += 1,
+                                    Ok((false, _)) => {}
+                                    Err(e) => {
+                                        warn!("Failed to insert post {}: {e}", post.id);
+                                        self.insert_failed(post.id, page, &mut skipped).await?;
+                                    }
+                                }
+                            }
+                        }
+                    }
+                }
             }
+            // after the posts, so comments of posts that failed to insert are dropped
+            if !comments.is_empty() {
+                new_comments += self.context.database.insert_comments(&comments).await?;
+            }
+            page += 1;
+            self.save_checkpoint(page);
         }
 
-        Ok(posts)
-    }
+        progress.finish_and_clear();
 
-    async fn fetch_posts(&self, page: u32) -> Result<FetchResult> {
-        let creator_id = self.args.creator_id;
-        let creator_name = &self.args.creator_name;
-        info!("Fetching posts for creator {creator_name} ({creator_id}), page {page}");
+        let scanned_pages = page - start_page;
+        if hit_page_cap || hit_deadline {
+            if let Some(path) = &self.args.checkpoint {
+                println!("The next run continues at page {page}, see {path}");
+            }
+        } else if let Some(path) = &self.args.checkpoint {
+            // the run got to the end, the next one starts from the first page again
+            if let Err(e) = std::fs::remove_file(path) {
+                if e.kind() != std::io::ErrorKind::NotFound {
+                    warn!("failed to remove checkpoint {path}: {e}");
+                }
+            }
+        }
+        if hit_page_cap {
+            println!(
+                "Stopped after {scanned_pages} pages because of --max-pages. If the creator doesn't have that many pages, check that the cookie is still valid."
+            );
+        }
+        if hit_deadline {
+            println!("Reached the deadline after {scanned_pages} pages and {new_posts} new posts");
+        }
+        let stats = RunStats::new("metadata", started_at, started.elapsed(), scraped_posts);
+        println!("Scraped {} from {scanned_pages} pages", stats.describe());
+        if let Err(e) = self.context.database.insert_run(&stats).await {
+            warn!("failed to store the run statistics: {e}");
+        }
+        if self.args.refetch_metadata {
+            println!("Added {new_posts} new posts and updated {updated_posts} existing ones");
+        }
+        if self.args.with_comments {
+            println!("Stored {new_comments} new comments");
+        }
+        let skipped_count: usize = skipped.values().sum();
+        if skipped_count > 0 {
+            let reasons: Vec<String> = skipped
+                .iter()
+                .map(|(reason, count)| format!("{count} {}", reason.description()))
+                .collect();
+            println!("Skipped {skipped_count} posts ({})", reasons.join(", "));
+        }
 
-        let url = format!("https://hutt.co/hutts/ajax-posts?page={page}&view=view&id={creator_id}");
-        let response = self
-            .context
-            .client
-            .get(&url)
-            .header("Cookie", &self.args.cookie)
-            .header("User-Agent", USER_AGENT)
-            .send()
-            .await?;
-        if response.status() == StatusCode::TOO_MANY_REQUESTS {
-            return Ok(FetchResult::RateLimited);
-        } else {
-            let text = response.text().await?;
-            let posts = self.scrape_posts(text, creator_name)?;
-            Ok(FetchResult::Posts(posts))
+        let failed_galleries = self.scraper.failed_galleries();
+        if failed_galleries > 0 {
+            warn!("Failed to parse {failed_galleries} image galleries");
+            println!(
+                "Failed to parse {failed_galleries} image galleries, see the log for details."
+            );
         }
+
+        let failed = skipped
+            .get(&SkipReason::InsertFailed)
+            .copied()
+            .unwrap_or_default();
+        Ok(MetadataStats {
+            scraped: scraped_posts,
+            new: new_posts,
+            skipped: skipped_count - failed,
+            failed,
+        })
     }
 
-    async fn run(&self) -> Result<()> {
-        use tokio::time;
+    /// Fetches the pages again and stores the posts that weren't stored before, e.g. because an
+    /// older version of the scraper couldn't find their links.
+    async fn rescan(&self, pages: RangeInclusive<u32>) -> Result<()> {
+        let mut posts_added = 0;
+        let mut links_added = 0;
+        let mut without_media = 0;
+        let mut extraction_failures = vec![];
 
-        let mut page = 0;
-        loop {
-            let posts = self.fetch_posts(page).await?;
-            match posts {
-                FetchResult::RateLimited => {
-                    warn!("Rate limited, sleeping for 2 minutes");
-                    time::sleep(std::time::Duration::from_secs(120)).await;
-                    continue;
+        for page in pages {
+            let scraped = self
+                .fetch_page_with_retries(page, &ProgressBar::hidden())
+                .await?;
+
+            for skipped_post in scraped.skipped {
+                match skipped_post.reason {
+                    SkipReason::MissingPostType => without_media += 1,
+                    SkipReason::NoLinks => extraction_failures.push(
+                        skipped_post
+                            .post_id
+                            .clone()
+                            .unwrap_or_else(|| format!("unknown post on page {page}")),
+                    ),
+                    _ => {}
                 }
-                FetchResult::Posts(posts) => {
-                    if posts.is_empty() {
-                        info!("No more posts found, stopping");
-                        break;
-                    }
-                    for post in &posts {
-                        self.context.database.insert_post(post).await?;
-                    }
-                    page += 1;
+                self.context
+                    .database
+                    .insert_scrape_error(
+                        skipped_post.post_id.as_deref(),
+                        page.into(),
+                        skipped_post.reason.description(),
+                    )
+                    .await?;
+            }
+            for post in &scraped.posts {
+                let (post_added, new_links) =
+                    self.context.database.insert_post_if_new(post).await?;
+                if post_added {
+                    info!("Recovered post {} on page {page}", post.id);
+                    posts_added += 1;
                 }
+                links_added += new_links;
             }
         }
 
+        println!("Recovered {posts_added} posts and {links_added} links");
+        if without_media > 0 {
+            println!("{without_media} posts have no media");
+        }
+        if !extraction_failures.is_empty() {
+            println!(
+                "{} posts have media but no links could be extracted: {}",
+                extraction_failures.len(),
+                extraction_failures.join(", ")
+            );
+        }
+
         Ok(())
     }
 }
 
-pub async fn run(context: DownloadContext, args: MetadataArgs) -> Result<()> {
-    let creator = PostFetcher {
+fn post_fetcher(context: DownloadContext, args: MetadataArgs) -> Result<PostFetcher> {
+    let selectors = Selectors::from_config(&context.configuration)?;
+    let mut scraper = PostScraper::new(selectors);
+    scraper.skip_type = args.skip_type;
+    scraper.with_comments = args.with_comments;
+    scraper.post_authors = args.liked;
+    scraper.html_directory = args
+        .save_html
+        .then(|| context.configuration.download_directory().join("posts"));
+    Ok(PostFetcher {
         context,
         args,
-        selectors: Selectors::new(),
-        url_extractor: UrlExtractor {},
-    };
+        scraper,
+    })
+}
+
+pub async fn run(context: DownloadContext, args: MetadataArgs) -> Result<MetadataStats> {
+    post_fetcher(context, args)?.run().await
+}
+
+/// Scrapes the given pages again and stores any posts that are missing from the database.
+pub async fn rescan(
+    context: DownloadContext,
+    args: MetadataArgs,
+    pages: RangeInclusive<u32>,
+) -> Result<()> {
+    post_fetcher(context, args)?.rescan(pages).await
+}
 
-    creator.run().await
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use camino::Utf8PathBuf;
+    use chrono::{DateTime, Utc};
+
+    use super::{parse_retry_after, Checkpoint};
+
+    #[test]
+    fn test_parse_retry_after() {
+        let now: DateTime<Utc> = "2015-10-21T07:27:00Z".parse().unwrap();
+        assert_eq!(parse_retry_after("30", now), Some(Duration::from_secs(30)));
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:28:00 GMT", now),
+            Some(Duration::from_secs(60))
+        );
+        assert_eq!(
+            parse_retry_after("Wed, 21 Oct 2015 07:00:00 GMT", now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(parse_retry_after("soon", now), None);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let directory =
+            std::env::temp_dir().join(format!("hutt-checkpoint-{}", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let path = Utf8PathBuf::try_from(directory.join(".hutt-scrape-state")).unwrap();
+
+        assert_eq!(Checkpoint::load(&path, 1), None);
+        let checkpoint = Checkpoint {
+            creator_id: 1,
+            page: 42,
+        };
+        checkpoint.save(&path).unwrap();
+        assert_eq!(Checkpoint::load(&path, 1), Some(42));
+        // checkpoints don't carry over to other creators
+        assert_eq!(Checkpoint::load(&path, 2), None);
+
+        std::fs::write(&path, "not json").unwrap();
+        assert_eq!(Checkpoint::load(&path, 1), None);
+        std::fs::remove_dir_all(&directory).unwrap();
+    }
 }
@@ -1,4 +1,12 @@
+pub mod disk_usage;
+pub mod doctor;
 pub mod download;
+pub mod import;
+pub mod init;
+pub mod manifest;
 pub mod metadata;
+pub mod profile;
 pub mod rename;
+pub mod reparse;
+pub mod resolve_creator;
 pub mod set_dates;
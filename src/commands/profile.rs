@@ -0,0 +1,223 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use reqwest::Url;
+use scraper::{Html, Selector};
+use tracing::{info, warn};
+
+use crate::commands::metadata::USER_AGENT;
+use crate::cookies::is_hutt_url;
+use crate::database::CreatorProfile;
+use crate::{DownloadContext, Result};
+
+#[derive(Debug, Default, PartialEq)]
+struct ProfileAssets {
+    avatar: Option<String>,
+    banner: Option<String>,
+    bio: Option<String>,
+}
+
+/// The URL in a `background-image: url(...)` style.
+fn background_image(style: &str) -> Option<&str> {
+    let start = style.find("url(")? + "url(".len();
+    let end = start + style[start..].find(')')?;
+    let url = style[start..end].trim().trim_matches(['"', '\'']);
+    (!url.is_empty()).then_some(url)
+}
+
+/// The first element matching one of `selectors` that has a value for `attr`.
+fn find_attr(document: &Html, selectors: &[&str], attr: &str) -> Option<String> {
+    selectors.iter().find_map(|selector| {
+        let selector = Selector::parse(selector).unwrap();
+        document
+            .select(&selector)
+            .find_map(|element| element.attr(attr))
+            .map(|value| value.trim().to_string())
+            .filter(|value| !value.is_empty())
+    })
+}
+
+fn parse_profile(html: &str, url: &Url) -> ProfileAssets {
+    let document = Html::parse_document(html);
+    let absolute = |value: String| url.join(&value).map(String::from).ok();
+
+    let avatar = find_attr(
+        &document,
+        &[".profile-avatar img", "img.profile-avatar", "img.avatar"],
+        "src",
+    )
+    .or_else(|| find_attr(&document, &[r#"meta[property="og:image"]"#], "content"))
+    .and_then(absolute);
+
+    let banner = find_attr(
+        &document,
+        &[".profile-banner img", ".cover-photo img", "img.banner"],
+        "src",
+    )
+    .or_else(|| {
+        find_attr(&document, &[".profile-banner", ".cover-photo"], "style")
+            .and_then(|style| background_image(&style).map(ToOwned::to_owned))
+    })
+    .and_then(absolute);
+
+    let bio_selector = Selector::parse(".profile-bio, .bio").unwrap();
+    let bio = document
+        .select(&bio_selector)
+        .next()
+        .map(|element| {
+            element
+                .text()
+                .map(str::trim)
+                .filter(|line| !line.is_empty())
+                .collect::<Vec<_>>()
+                .join("\n")
+        })
+        .filter(|bio| !bio.is_empty())
+        .or_else(|| find_attr(&document, &[r#"meta[name="description"]"#], "content"));
+
+    ProfileAssets {
+        avatar,
+        banner,
+        bio,
+    }
+}
+
+/// Downloads `url` to `<directory>/<name>.<extension>` and returns the path. The session cookie
+/// is only sent along if the asset is hosted on hutt.co.
+async fn download_asset(
+    context: &DownloadContext,
+    url: &str,
+    directory: &Utf8Path,
+    name: &str,
+) -> Result<Utf8PathBuf> {
+    let url = Url::parse(url)?;
+    let extension = url
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .and_then(|file| Utf8Path::new(file).extension())
+        .map(ToOwned::to_owned)
+        .unwrap_or_else(|| "jpg".to_string());
+    let path = directory.join(format!("{name}.{extension}"));
+
+    let mut request = context
+        .client
+        .get(url.clone())
+        .header("User-Agent", USER_AGENT);
+    if is_hutt_url(&url) {
+        request = request.header("Cookie", &context.configuration.cookie);
+    }
+    let bytes = request.send().await?.error_for_status()?.bytes().await?;
+    tokio::fs::write(&path, &bytes).await?;
+    info!("downloaded {url} to {path}");
+
+    Ok(path)
+}
+
+pub async fn run(context: DownloadContext) -> Result<()> {
+    let configuration = &context.configuration;
+    let url = Url::parse(&format!("https://hutt.co/{}", configuration.creator_name))?;
+    let html = context
+        .client
+        .get(url.clone())
+        .header("Cookie", &configuration.cookie)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()?
+        .text()
+        .await?;
+    let assets = parse_profile(&html, &url);
+
+    let directory = configuration.download_directory().join("profile");
+    tokio::fs::create_dir_all(&directory).await?;
+
+    let mut profile = CreatorProfile {
+        name: configuration.creator_name.clone(),
+        hutt_id: configuration.creator_id,
+        bio: assets.bio,
+        avatar_path: None,
+        banner_path: None,
+    };
+    if let Some(avatar) = &assets.avatar {
+        match download_asset(&context, avatar, &directory, "avatar").await {
+            Ok(path) => profile.avatar_path = Some(path.to_string()),
+            Err(e) => warn!("failed to download avatar {avatar}: {e}"),
+        }
+    }
+    if let Some(banner) = &assets.banner {
+        match download_asset(&context, banner, &directory, "banner").await {
+            Ok(path) => profile.banner_path = Some(path.to_string()),
+            Err(e) => warn!("failed to download banner {banner}: {e}"),
+        }
+    }
+    if let Some(bio) = &profile.bio {
+        tokio::fs::write(directory.join("bio.txt"), bio).await?;
+    }
+    context.database.upsert_creator_profile(&profile).await?;
+
+    println!("Saved the profile of {} to {directory}", profile.name);
+    for (name, value) in [
+        ("avatar", &profile.avatar_path),
+        ("banner", &profile.banner_path),
+        ("bio", &profile.bio),
+    ] {
+        if value.is_none() {
+            println!("No {name} found");
+        }
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{background_image, parse_profile, ProfileAssets};
+
+    #[test]
+    fn test_parse_profile() {
+        let html = r#"<html><head>
+            <meta property="og:image" content="https://cdn.hutt.co/og.jpg">
+        </head><body>
+            <div class="profile-banner" style="background-image: url('/banners/1.jpg')"></div>
+            <div class="profile-avatar"><img src="/avatars/1.png"></div>
+            <div class="profile-bio"><p>Hi there!</p> <p>Posting daily.</p></div>
+        </body></html>"#;
+        let url = Url::parse("https://hutt.co/someone").unwrap();
+
+        assert_eq!(
+            parse_profile(html, &url),
+            ProfileAssets {
+                avatar: Some("https://hutt.co/avatars/1.png".into()),
+                banner: Some("https://hutt.co/banners/1.jpg".into()),
+                bio: Some("Hi there!\nPosting daily.".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_profile_fallbacks() {
+        let html = r#"<html><head>
+            <meta property="og:image" content="https://cdn.hutt.co/og.jpg">
+            <meta name="description" content="Just a creator">
+        </head><body></body></html>"#;
+        let url = Url::parse("https://hutt.co/someone").unwrap();
+
+        assert_eq!(
+            parse_profile(html, &url),
+            ProfileAssets {
+                avatar: Some("https://cdn.hutt.co/og.jpg".into()),
+                banner: None,
+                bio: Some("Just a creator".into()),
+            }
+        );
+    }
+
+    #[test]
+    fn test_background_image() {
+        assert_eq!(
+            background_image(r#"background-image: url("/a.jpg");"#),
+            Some("/a.jpg")
+        );
+        assert_eq!(background_image("color: red"), None);
+    }
+}
@@ -1,20 +1,68 @@
-use camino::Utf8Path;
+use std::collections::{BTreeMap, BTreeSet, HashSet};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::bail;
+use serde::Serialize;
 use tracing::{debug, info, warn};
 
 use crate::database::LinkStatus;
+use crate::filenames::DateBucket;
 use crate::{filenames, DownloadContext, Result};
 
+pub struct RenameArgs {
+    pub dry_run: bool,
+    pub plan: Option<Utf8PathBuf>,
+    /// Copies the files instead of moving them, so the old layout stays intact.
+    pub copy: bool,
+    /// Sorts the files into date directories below the download directory.
+    pub bucket_by: Option<DateBucket>,
+    /// Refuses to merge or empty directories instead of only warning about it.
+    pub preserve_structure: bool,
+}
+
+#[derive(Debug, Serialize)]
+struct PlannedMove {
+    link_id: i64,
+    old_path: String,
+    new_path: String,
+    #[serde(skip)]
+    pattern: String,
+}
+
+/// Effects of a rename that can lose data or leave a confusing layout behind.
+#[derive(Debug, Default, Serialize)]
+struct LayoutChanges {
+    /// Paths that several files would be moved to, or that are taken by a file that stays.
+    collisions: Vec<String>,
+    /// Directories that would get files from more than one directory.
+    merged_directories: Vec<String>,
+    /// Directories that files are moved out of and none into.
+    emptied_directories: Vec<String>,
+}
+
+#[derive(Debug, Default, Serialize)]
+struct RenamePlan {
+    already_renamed: usize,
+    moves: Vec<PlannedMove>,
+    changes: LayoutChanges,
+}
+
 async fn do_rename(
     link_id: i64,
     current_path: &Utf8Path,
     new_path: &Utf8Path,
     pattern: &str,
+    copy: bool,
     context: &DownloadContext,
 ) -> Result<()> {
     let parent = new_path.parent().expect("must have parent");
 
     tokio::fs::create_dir_all(parent).await?;
-    tokio::fs::rename(&current_path, &new_path).await?;
+    if copy {
+        tokio::fs::copy(&current_path, &new_path).await?;
+    } else {
+        tokio::fs::rename(&current_path, &new_path).await?;
+    }
     let db_result = context
         .database
         .update_path(link_id, new_path.as_str(), pattern)
@@ -24,7 +72,11 @@ async fn do_rename(
             "failed to update database for link ID {}, rolling back rename",
             link_id
         );
-        tokio::fs::rename(&new_path, &current_path).await?;
+        if copy {
+            tokio::fs::remove_file(&new_path).await?;
+        } else {
+            tokio::fs::rename(&new_path, &current_path).await?;
+        }
         return Err(e);
     }
 
@@ -34,7 +86,7 @@ async fn do_rename(
 fn remove_empty_directories(base_path: &Utf8Path) -> Result<()> {
     use walkdir::WalkDir;
 
-    for entry in WalkDir::new(&base_path) {
+    for entry in WalkDir::new(base_path) {
         let entry = entry?;
         if entry.path().is_dir() {
             let is_empty = entry.path().read_dir()?.next().is_none();
@@ -48,11 +100,86 @@ fn remove_empty_directories(base_path: &Utf8Path) -> Result<()> {
     Ok(())
 }
 
-pub async fn run(dry_run: bool, context: DownloadContext) -> Result<()> {
-    let posts = context.database.fetch_all().await?;
+/// Flags destructive effects of the moves: files that would overwrite each other or files that
+/// haven't been moved away yet when the move runs (`exists` tells whether a path is taken on disk
+/// before the rename), directories that get files from several others and, unless copying,
+/// directories that are left behind.
+fn analyze(moves: &[PlannedMove], exists: impl Fn(&Utf8Path) -> bool, copy: bool) -> LayoutChanges {
+    let mut targets: BTreeMap<&str, usize> = BTreeMap::new();
+    for planned in moves {
+        *targets.entry(&planned.new_path).or_default() += 1;
+    }
+    // the moves run one after the other, so a taken path is only free once its file moved away
+    let mut vacated = HashSet::new();
+    let mut collisions = BTreeSet::new();
+    for planned in moves {
+        let target = planned.new_path.as_str();
+        if targets[target] > 1 || (exists(Utf8Path::new(target)) && !vacated.contains(target)) {
+            collisions.insert(target.to_string());
+        }
+        if !copy {
+            vacated.insert(planned.old_path.as_str());
+        }
+    }
+    let collisions = collisions.into_iter().collect();
+
+    let parent = |path: &str| {
+        Utf8Path::new(path)
+            .parent()
+            .map(|parent| parent.to_string())
+            .unwrap_or_default()
+    };
+    let mut sources: BTreeMap<String, BTreeSet<String>> = BTreeMap::new();
+    for planned in moves {
+        sources
+            .entry(parent(&planned.new_path))
+            .or_default()
+            .insert(parent(&planned.old_path));
+    }
+    let merged_directories = sources
+        .iter()
+        .filter(|(_, from)| from.len() > 1)
+        .map(|(directory, _)| directory.clone())
+        .collect();
+    let emptied_directories = if copy {
+        vec![]
+    } else {
+        moves
+            .iter()
+            .map(|planned| parent(&planned.old_path))
+            .filter(|directory| !sources.contains_key(directory))
+            .collect::<BTreeSet<_>>()
+            .into_iter()
+            .collect()
+    };
+
+    LayoutChanges {
+        collisions,
+        merged_directories,
+        emptied_directories,
+    }
+}
+
+/// Prints up to a few of `paths` below `message`.
+fn print_paths(message: &str, paths: &[String]) {
+    const MAX_EXAMPLES: usize = 5;
+
+    println!("{message}:");
+    for path in paths.iter().take(MAX_EXAMPLES) {
+        println!("  {path}");
+    }
+    if paths.len() > MAX_EXAMPLES {
+        println!("  ... and {} more", paths.len() - MAX_EXAMPLES);
+    }
+}
+
+pub async fn run(context: DownloadContext, args: RenameArgs) -> Result<()> {
+    let mut posts = context.database.stream_all();
     let filename_patterns = context.configuration.filename_pattern();
+    let extensions = context.configuration.extension_map();
+    let mut plan = RenamePlan::default();
 
-    for post in &posts {
+    while let Some(post) = posts.next_post().await? {
         for link in &post.links {
             if link.status == LinkStatus::Downloaded {
                 let current_path = link
@@ -65,8 +192,15 @@ pub async fn run(dry_run: bool, context: DownloadContext) -> Result<()> {
                 let new_path = filenames::get_download_path(
                     &post,
                     link.id,
+                    link.original_name.as_deref(),
+                    &link.content_type,
+                    &extensions,
                     pattern,
-                    context.configuration.download_directory(),
+                    filenames::bucket_directory(
+                        context.configuration.download_directory(),
+                        &post,
+                        args.bucket_by,
+                    ),
                 );
 
                 if current_path != new_path {
@@ -75,18 +209,145 @@ pub async fn run(dry_run: bool, context: DownloadContext) -> Result<()> {
                         continue;
                     }
                     info!("'{}' -> '{}'", current_path, new_path);
-                    if !dry_run {
-                        do_rename(link.id, current_path, &new_path, &pattern, &context).await?;
-                    }
+                    plan.moves.push(PlannedMove {
+                        link_id: link.id,
+                        old_path: current_path.to_string(),
+                        new_path: new_path.to_string(),
+                        pattern: pattern.clone(),
+                    });
                 } else {
                     debug!("skipping {} as it is already renamed", current_path);
+                    plan.already_renamed += 1;
                 }
             }
         }
     }
 
-    if !dry_run {
+    // nothing is moved before the whole new layout is known
+    plan.changes = analyze(&plan.moves, |path| path.is_file(), args.copy);
+    let changes = &plan.changes;
+    if !changes.merged_directories.is_empty() {
+        print_paths(
+            "These directories would get files from several directories",
+            &changes.merged_directories,
+        );
+    }
+    if !changes.emptied_directories.is_empty() {
+        print_paths(
+            "All files would be moved out of these directories",
+            &changes.emptied_directories,
+        );
+    }
+    if !changes.collisions.is_empty() {
+        print_paths(
+            "Several files would end up at these paths, or they are taken already",
+            &changes.collisions,
+        );
+    }
+
+    if let Some(plan_path) = &args.plan {
+        info!(
+            "writing rename plan with {} moves ({} already renamed) to {}",
+            plan.moves.len(),
+            plan.already_renamed,
+            plan_path
+        );
+        let json = serde_json::to_string_pretty(&plan)?;
+        std::fs::write(plan_path, json)?;
+    }
+    if args.dry_run {
+        return Ok(());
+    }
+    if !plan.changes.collisions.is_empty() {
+        bail!(
+            "refusing to rename, {} files would overwrite other files. Change the filename pattern so the names are unique, e.g. with {{link_id}}",
+            plan.changes.collisions.len()
+        );
+    }
+    if args.preserve_structure
+        && !(plan.changes.merged_directories.is_empty()
+            && plan.changes.emptied_directories.is_empty())
+    {
+        bail!("refusing to rename, the new pattern changes the directory structure");
+    }
+
+    for planned in &plan.moves {
+        do_rename(
+            planned.link_id,
+            Utf8Path::new(&planned.old_path),
+            Utf8Path::new(&planned.new_path),
+            &planned.pattern,
+            args.copy,
+            &context,
+        )
+        .await?;
+    }
+
+    // copies leave the old files in place, so no directory became empty
+    if !args.copy {
         remove_empty_directories(context.configuration.download_directory())?;
     }
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use camino::Utf8Path;
+
+    use super::{analyze, PlannedMove};
+
+    fn planned(old_path: &str, new_path: &str) -> PlannedMove {
+        PlannedMove {
+            link_id: 1,
+            old_path: old_path.to_string(),
+            new_path: new_path.to_string(),
+            pattern: String::new(),
+        }
+    }
+
+    #[test]
+    fn test_analyze_flatten() {
+        let moves = [
+            planned("Images/1 - a/10.jpeg", "Images/1 - a - 10.jpeg"),
+            planned("Images/2 - b/20.jpeg", "Images/2 - b - 20.jpeg"),
+        ];
+        let changes = analyze(&moves, |_| false, false);
+
+        assert!(changes.collisions.is_empty());
+        assert_eq!(changes.merged_directories, vec!["Images"]);
+        assert_eq!(
+            changes.emptied_directories,
+            vec!["Images/1 - a", "Images/2 - b"]
+        );
+        assert!(analyze(&moves, |_| false, true)
+            .emptied_directories
+            .is_empty());
+    }
+
+    #[test]
+    fn test_analyze_collisions() {
+        let moves = [
+            planned("Images/1/10.jpeg", "Images/1.jpeg"),
+            planned("Images/1/11.jpeg", "Images/1.jpeg"),
+            planned("Images/2/20.jpeg", "Images/2.jpeg"),
+            // swapping two files overwrites the second one before it's moved
+            planned("a.jpeg", "b.jpeg"),
+            planned("b.jpeg", "a.jpeg"),
+            // a chain is fine if the file at the target is moved away first
+            planned("d.jpeg", "e.jpeg"),
+            planned("c.jpeg", "d.jpeg"),
+        ];
+        let taken = |path: &Utf8Path| {
+            ["Images/2.jpeg", "a.jpeg", "b.jpeg", "c.jpeg", "d.jpeg"].contains(&path.as_str())
+        };
+        let changes = analyze(&moves, taken, false);
+
+        assert_eq!(
+            changes.collisions,
+            vec!["Images/1.jpeg", "Images/2.jpeg", "b.jpeg"]
+        );
+        // copies leave every file in place
+        let changes = analyze(&moves, taken, true);
+        assert!(changes.collisions.contains(&"d.jpeg".to_string()));
+    }
+}
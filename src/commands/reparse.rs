@@ -0,0 +1,94 @@
+use camino::{Utf8Path, Utf8PathBuf};
+use tracing::{info, warn};
+
+use crate::scraping::{PostScraper, Selectors};
+use crate::{DownloadContext, Result};
+
+pub struct ReparseArgs {
+    pub dir: Utf8PathBuf,
+}
+
+/// All `.html` files in `dir`, sorted by name so runs are reproducible.
+fn html_files(dir: &Utf8Path) -> Result<Vec<Utf8PathBuf>> {
+    let mut files = vec![];
+    for entry in dir.read_dir_utf8()? {
+        let path = entry?.into_path();
+        if path.extension() == Some("html") {
+            files.push(path);
+        }
+    }
+    files.sort();
+    Ok(files)
+}
+
+pub async fn run(context: DownloadContext, args: ReparseArgs) -> Result<()> {
+    let selectors = Selectors::from_config(&context.configuration)?;
+    let scraper = PostScraper::new(selectors);
+    let creator_name = &context.configuration.creator_name;
+
+    let mut posts_added = 0;
+    let mut links_added = 0;
+    let mut skipped = 0;
+    for path in html_files(&args.dir)? {
+        info!("reparsing {path}");
+        let text = tokio::fs::read_to_string(&path).await?;
+        let scraped = scraper.scrape_posts(text, creator_name, None);
+        skipped += scraped.skipped.len();
+
+        for post in &scraped.posts {
+            let (post_added, new_links) = context.database.insert_post_if_new(post).await?;
+            if post_added {
+                info!("added post {}", post.id);
+                posts_added += 1;
+            }
+            if new_links > 0 {
+                info!("added {new_links} links to post {}", post.id);
+            }
+            links_added += new_links;
+        }
+    }
+
+    let failed_galleries = scraper.failed_galleries();
+    if failed_galleries > 0 {
+        warn!("Failed to parse {failed_galleries} image galleries");
+    }
+
+    println!(
+        "Reparsed {}: added {posts_added} posts and {links_added} links, skipped {skipped} posts",
+        args.dir
+    );
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sqlx::SqlitePool;
+
+    use super::ReparseArgs;
+    use crate::database::Database;
+    use crate::{Configuration, DownloadContext, Result};
+
+    #[sqlx::test]
+    async fn test_reparse_fixtures(pool: SqlitePool) -> Result<()> {
+        let reparse = || async {
+            let context = DownloadContext::new(pool.clone(), Configuration::test())?;
+            let args = ReparseArgs {
+                dir: "fixtures".into(),
+            };
+            super::run(context, args).await
+        };
+        reparse().await?;
+        // running it again doesn't add anything twice
+        reparse().await?;
+
+        let database = Database::new(pool);
+        let image_post = database.fetch_by_id(1001).await?.unwrap();
+        assert_eq!(image_post.links.len(), 3);
+        assert_eq!(image_post.source_page, None);
+        let video_post = database.fetch_by_id(1002).await?.unwrap();
+        assert_eq!(video_post.links.len(), 1);
+
+        Ok(())
+    }
+}
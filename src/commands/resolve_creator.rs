@@ -0,0 +1,131 @@
+use color_eyre::eyre::{bail, eyre};
+use regex::Regex;
+use reqwest::{Client, Url};
+use scraper::{Html, Selector};
+
+use crate::commands::metadata::USER_AGENT;
+use crate::cookies::is_hutt_url;
+use crate::{DownloadContext, Result};
+
+pub struct ResolveCreatorArgs {
+    pub url: String,
+}
+
+#[derive(Debug, PartialEq)]
+pub struct ResolvedCreator {
+    pub id: i64,
+    pub name: String,
+}
+
+fn find_creator_id(html: &str) -> Option<i64> {
+    // the profile page polls `is-live?id=...` and loads posts via `ajax-posts?...&id=...`
+    let patterns = [
+        r"is-live\?id=(\d+)",
+        r"ajax-posts\?[^'\x22]*?[?&]id=(\d+)",
+        r#"data-(?:creator|hutt|user)-id="(\d+)""#,
+    ];
+    patterns.iter().find_map(|pattern| {
+        let regex = Regex::new(pattern).unwrap();
+        regex
+            .captures(html)
+            .and_then(|captures| captures[1].parse().ok())
+    })
+}
+
+fn find_creator_name(document: &Html, url: &Url) -> Option<String> {
+    let og_title = Selector::parse(r#"meta[property="og:title"]"#).unwrap();
+    let title = Selector::parse("title").unwrap();
+
+    document
+        .select(&og_title)
+        .next()
+        .and_then(|element| element.value().attr("content"))
+        .map(ToOwned::to_owned)
+        .or_else(|| {
+            document
+                .select(&title)
+                .next()
+                .map(|element| element.text().collect::<String>())
+        })
+        .map(|name| {
+            // titles look like `name | Hutt`
+            name.split('|')
+                .next()
+                .unwrap_or_default()
+                .trim()
+                .to_string()
+        })
+        .filter(|name| !name.is_empty())
+        .or_else(|| {
+            url.path_segments()?
+                .rev()
+                .find(|segment| !segment.is_empty())
+                .map(ToOwned::to_owned)
+        })
+}
+
+fn parse_profile(html: &str, url: &Url) -> Result<ResolvedCreator> {
+    let id = find_creator_id(html).ok_or_else(|| eyre!("no creator id found on {url}"))?;
+    let document = Html::parse_document(html);
+    let name = find_creator_name(&document, url).ok_or_else(|| eyre!("no name found on {url}"))?;
+
+    Ok(ResolvedCreator { id, name })
+}
+
+/// Fetches a creator's profile page and finds their id and name on it.
+pub async fn resolve(client: &Client, cookie: &str, url: &str) -> Result<ResolvedCreator> {
+    let url = Url::parse(url)?;
+    if !is_hutt_url(&url) {
+        bail!("`{url}` is not a hutt.co profile URL");
+    }
+
+    let response = client
+        .get(url.clone())
+        .header("Cookie", cookie)
+        .header("User-Agent", USER_AGENT)
+        .send()
+        .await?
+        .error_for_status()?;
+    let html = response.text().await?;
+    parse_profile(&html, &url)
+}
+
+pub async fn run(context: DownloadContext, args: ResolveCreatorArgs) -> Result<()> {
+    let creator = resolve(&context.client, &context.configuration.cookie, &args.url).await?;
+
+    println!("creatorId: {},", creator.id);
+    println!("creatorName: {:?},", creator.name);
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{parse_profile, ResolvedCreator};
+
+    #[test]
+    fn test_parse_profile() {
+        let html = r#"<html><head>
+            <title>someone | Hutt</title>
+            <script>setInterval(() => fetch("/hutts/is-live?id=12345"), 30000)</script>
+        </head><body></body></html>"#;
+        let url = Url::parse("https://hutt.co/someone").unwrap();
+
+        let creator = parse_profile(html, &url).unwrap();
+        assert_eq!(
+            creator,
+            ResolvedCreator {
+                id: 12345,
+                name: "someone".into()
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_profile_without_id() {
+        let url = Url::parse("https://hutt.co/someone").unwrap();
+        assert!(parse_profile("<html></html>", &url).is_err());
+    }
+}
@@ -1,20 +1,80 @@
-use chrono::NaiveDate;
+use chrono::{NaiveDate, NaiveTime};
+use clap::ValueEnum;
 use color_eyre::eyre::bail;
 use tracing::info;
 
 use crate::{DownloadContext, Result};
 
+/// How posts are spread out between the start and end date.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum DateStrategy {
+    /// Evenly by position, as if posts were made at a constant rate.
+    #[default]
+    Index,
+    /// Proportionally to the gaps between post ids, which grow over time.
+    Id,
+}
+
+/// How positions are mapped to dates, to cluster posts at one end of the range.
+#[derive(Debug, Clone, Copy, Default, ValueEnum)]
+pub enum DateCurve {
+    /// Maps positions to dates one to one.
+    #[default]
+    Linear,
+    /// Clusters posts towards the start date.
+    EaseIn,
+    /// Clusters posts towards the end date.
+    EaseOut,
+}
+
+impl DateCurve {
+    fn apply(self, percentage: f64) -> f64 {
+        match self {
+            DateCurve::Linear => percentage,
+            DateCurve::EaseIn => percentage * percentage,
+            DateCurve::EaseOut => 1.0 - (1.0 - percentage) * (1.0 - percentage),
+        }
+    }
+}
+
 pub struct SetDatesArgs {
     pub start: String,
     pub end: String,
+    pub dry_run: bool,
+    pub strategy: DateStrategy,
+    pub curve: DateCurve,
+    pub force: bool,
 }
 
-fn lerp_dates(start: NaiveDate, end: NaiveDate, percentage: f64) -> NaiveDate {
+fn lerp_dates(start: NaiveDate, end: NaiveDate, percentage: f64, curve: DateCurve) -> NaiveDate {
     let days = (end - start).num_days() as f64;
-    let days = days * percentage;
+    let days = days * curve.apply(percentage);
     start + chrono::Duration::days(days as i64)
 }
 
+/// Position of every post between the start (0.0) and end (1.0) date. `ids` must be sorted.
+fn percentages(ids: &[i64], strategy: DateStrategy) -> Vec<f64> {
+    let len = ids.len() as f64;
+    match strategy {
+        DateStrategy::Index => (0..ids.len()).map(|index| index as f64 / len).collect(),
+        DateStrategy::Id => {
+            let (Some(first), Some(last)) = (ids.first(), ids.last()) else {
+                return vec![];
+            };
+            let range = (last - first) as f64;
+            ids.iter()
+                .map(|id| {
+                    if range > 0.0 {
+                        (id - first) as f64 / range
+                    } else {
+                        0.0
+                    }
+                })
+                .collect()
+        }
+    }
+}
+
 pub async fn run(context: DownloadContext, args: SetDatesArgs) -> Result<()> {
     let start_date = NaiveDate::parse_from_str(&args.start, "%Y-%m-%d")?;
     let end_date = NaiveDate::parse_from_str(&args.end, "%Y-%m-%d")?;
@@ -25,13 +85,77 @@ pub async fn run(context: DownloadContext, args: SetDatesArgs) -> Result<()> {
 
     // interpolate start - end dates for all posts (just approximate)
     let all_posts = context.database.fetch_all().await?;
-    let len = all_posts.len() as f64;
-    for (index, post) in all_posts.into_iter().enumerate() {
-        let percentage = index as f64 / len;
-        let new_date = lerp_dates(start_date, end_date, percentage);
+    let ids: Vec<_> = all_posts.iter().map(|post| post.id).collect();
+    let percentages = percentages(&ids, args.strategy);
+    let mut already_dated = 0;
+    for (index, (post, percentage)) in all_posts.into_iter().zip(percentages).enumerate() {
+        // posts keep their position in the range, so skipping some doesn't shift the others
+        if post.created_at.is_some() && !args.force {
+            already_dated += 1;
+            continue;
+        }
+        let new_date = lerp_dates(start_date, end_date, percentage, args.curve);
+        if args.dry_run {
+            println!(
+                "{} -> {} (#{}, {:.1}%)",
+                post.id,
+                new_date,
+                index,
+                percentage * 100.0
+            );
+            continue;
+        }
         info!("setting post {} to date {}", post.id, new_date);
-        context.database.set_post_date(post.id, new_date).await?;
+        context
+            .database
+            .set_post_date(post.id, new_date.and_time(NaiveTime::MIN).and_utc())
+            .await?;
+    }
+
+    if already_dated > 0 {
+        println!("{already_dated} skipped (already dated), pass --force to overwrite them.");
     }
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use chrono::NaiveDate;
+
+    use super::{lerp_dates, percentages, DateCurve, DateStrategy};
+
+    #[test]
+    fn test_percentages_by_index() {
+        assert_eq!(
+            percentages(&[1, 2, 10, 11], DateStrategy::Index),
+            vec![0.0, 0.25, 0.5, 0.75]
+        );
+    }
+
+    #[test]
+    fn test_percentages_by_id() {
+        assert_eq!(
+            percentages(&[10, 20, 60, 110], DateStrategy::Id),
+            vec![0.0, 0.1, 0.5, 1.0]
+        );
+        assert_eq!(percentages(&[5], DateStrategy::Id), vec![0.0]);
+        assert!(percentages(&[], DateStrategy::Id).is_empty());
+    }
+
+    #[test]
+    fn test_lerp_dates_with_curve() {
+        let start = NaiveDate::from_ymd_opt(2024, 1, 1).unwrap();
+        let end = NaiveDate::from_ymd_opt(2024, 4, 10).unwrap();
+        let date =
+            |percentage, curve| (lerp_dates(start, end, percentage, curve) - start).num_days();
+
+        assert_eq!(date(0.5, DateCurve::Linear), 50);
+        assert_eq!(date(0.5, DateCurve::EaseIn), 25);
+        assert_eq!(date(0.5, DateCurve::EaseOut), 75);
+        for curve in [DateCurve::Linear, DateCurve::EaseIn, DateCurve::EaseOut] {
+            assert_eq!(date(0.0, curve), 0);
+            assert_eq!(date(1.0, curve), 100);
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use std::process::{Command, Stdio};
+
+use color_eyre::eyre::{bail, eyre};
+use reqwest::Url;
+use serde::{Deserialize, Serialize};
+
+use crate::Result;
+
+/// Browsers that cookies can be read from.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Browser {
+    Firefox,
+    Chrome,
+}
+
+impl Browser {
+    /// Name of the browser as yt-dlp's `--cookies-from-browser` expects it.
+    pub fn name(self) -> &'static str {
+        match self {
+            Browser::Firefox => "firefox",
+            Browser::Chrome => "chrome",
+        }
+    }
+}
+
+/// Where the session cookie comes from instead of the `cookie` string in the configuration.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy)]
+#[serde(rename_all = "camelCase")]
+pub enum CookieSource {
+    Browser(Browser),
+}
+
+/// Whether a cookie for `domain` is sent to hutt.co.
+fn is_hutt_domain(domain: &str) -> bool {
+    let domain = domain.trim_start_matches('.');
+    domain == "hutt.co" || domain.ends_with(".hutt.co")
+}
+
+/// Whether `url` points to hutt.co, the only host the session cookie may be sent to.
+pub(crate) fn is_hutt_url(url: &Url) -> bool {
+    url.host_str().is_some_and(is_hutt_domain)
+}
+
+/// Joins the hutt.co cookies of a Netscape cookie jar into a `Cookie` header value.
+fn parse_cookie_jar(content: &str) -> String {
+    content
+        .lines()
+        .filter_map(|line| {
+            // HttpOnly cookies are marked with a prefix, other `#` lines are comments
+            let line = line.strip_prefix("#HttpOnly_").unwrap_or(line);
+            if line.starts_with('#') {
+                return None;
+            }
+            let fields: Vec<_> = line.split('\t').collect();
+            match fields[..] {
+                [domain, _, _, _, _, name, value] if is_hutt_domain(domain) => {
+                    Some(format!("{name}={value}"))
+                }
+                _ => None,
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+impl CookieSource {
+    /// Reads the hutt.co cookies and joins them into a `Cookie` header value. yt-dlp does the
+    /// reading, since it knows how to decrypt the cookie stores of the browsers.
+    pub fn load(self) -> Result<String> {
+        let CookieSource::Browser(browser) = self;
+        // the jar holds the cookies of every site, so it goes into a directory only we can read
+        let directory = tempfile::TempDir::new()?;
+        let jar = directory.path().join("cookies.txt");
+        // without a URL yt-dlp exits with an error, but only after writing the cookie jar
+        let output = Command::new("yt-dlp")
+            .arg("--cookies-from-browser")
+            .arg(browser.name())
+            .arg("--cookies")
+            .arg(&jar)
+            .stdin(Stdio::null())
+            .output();
+        let content = std::fs::read_to_string(&jar);
+        drop(directory);
+
+        let output = output.map_err(|e| {
+            eyre!(
+                "failed to run yt-dlp to read cookies from {}: {e}",
+                browser.name()
+            )
+        })?;
+        let Ok(content) = content else {
+            bail!(
+                "failed to read cookies from {}: {}",
+                browser.name(),
+                String::from_utf8_lossy(&output.stderr).trim()
+            );
+        };
+        let cookie = parse_cookie_jar(&content);
+        if cookie.is_empty() {
+            bail!(
+                "no hutt.co cookies found in {}, are you logged in?",
+                browser.name()
+            );
+        }
+        Ok(cookie)
+    }
+
+    pub fn browser(self) -> Browser {
+        let CookieSource::Browser(browser) = self;
+        browser
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use reqwest::Url;
+
+    use super::{is_hutt_url, parse_cookie_jar};
+
+    #[test]
+    fn test_parse_cookie_jar() {
+        let jar = "# Netscape HTTP Cookie File\n\
+            \n\
+            .hutt.co\tTRUE\t/\tTRUE\t0\tsession\tabc\n\
+            #HttpOnly_hutt.co\tFALSE\t/\tTRUE\t0\ttoken\tdef\n\
+            .nothutt.co\tTRUE\t/\tTRUE\t0\tother\tghi\n\
+            .example.com\tTRUE\t/\tFALSE\t0\tid\t1\n";
+        assert_eq!(parse_cookie_jar(jar), "session=abc; token=def");
+        assert_eq!(parse_cookie_jar(""), "");
+    }
+
+    #[test]
+    fn test_is_hutt_url() {
+        let check = |url| is_hutt_url(&Url::parse(url).unwrap());
+        assert!(check("https://hutt.co/someone"));
+        assert!(check("https://www.hutt.co/someone"));
+        assert!(!check("https://nothutt.co/someone"));
+        assert!(!check("https://hutt.co.evil.com/someone"));
+    }
+}
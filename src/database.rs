@@ -1,11 +1,22 @@
-use std::collections::BTreeMap;
+use std::collections::HashSet;
+use std::time::Duration;
 
-use chrono::NaiveDate;
-use color_eyre::Result;
+use camino::Utf8Path;
+use chrono::{DateTime, NaiveDate, NaiveTime, Utc};
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, WrapErr};
+use color_eyre::{Report, Result};
+use futures_util::stream::{self, BoxStream};
+use futures_util::{StreamExt, TryStreamExt};
+use indicatif::HumanDuration;
 use serde::{Deserialize, Serialize};
 use sqlx::prelude::Type;
-use sqlx::SqlitePool;
-use tracing::info;
+use sqlx::sqlite::{SqliteConnectOptions, SqliteJournalMode, SqliteSynchronous};
+use sqlx::{SqliteConnection, SqlitePool};
+use tracing::{info, warn};
+
+use crate::filenames::url_basename;
+use crate::scraping::normalize_url;
 
 #[derive(Debug, Clone, Copy, Serialize, Deserialize, Type)]
 #[serde(rename_all = "kebab-case")]
@@ -15,13 +26,15 @@ pub enum LinkSource {
     HtmlString,
 }
 
-impl From<String> for LinkSource {
-    fn from(s: String) -> Self {
+impl TryFrom<String> for LinkSource {
+    type Error = Report;
+
+    fn try_from(s: String) -> Result<Self> {
         match s.as_str() {
-            "image-gallery" | "ImageGallery" => LinkSource::ImageGallery,
-            "video-post" | "VideoPost" => LinkSource::VideoPost,
-            "html-string" | "HtmlString" => LinkSource::HtmlString,
-            _ => panic!("Invalid link source: {}", s),
+            "image-gallery" | "ImageGallery" => Ok(LinkSource::ImageGallery),
+            "video-post" | "VideoPost" => Ok(LinkSource::VideoPost),
+            "html-string" | "HtmlString" => Ok(LinkSource::HtmlString),
+            _ => bail!("invalid link source `{s}`"),
         }
     }
 }
@@ -36,6 +49,12 @@ pub struct PostLink {
     pub error: Option<String>,
     pub file_path: Option<String>,
     pub file_path_pattern: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    /// The file name at the end of the URL, if it has one.
+    pub original_name: Option<String>,
+    /// The size the server reported for the file before it was downloaded.
+    pub expected_size: Option<i64>,
 }
 
 #[derive(Debug)]
@@ -45,38 +64,49 @@ pub struct CreatePostLink {
     pub source: LinkSource,
 }
 
+/// Stored in lowercase like the status updates write it, so queries can compare it directly.
 #[derive(Debug, Type, Clone, Copy, Serialize, Deserialize, PartialEq, Eq)]
 #[serde(rename_all = "lowercase")]
+#[sqlx(rename_all = "snake_case")]
 pub enum LinkStatus {
     Pending,
     Downloaded,
     Error,
+    #[serde(rename = "skipped_too_large")]
+    SkippedTooLarge,
 }
 
-impl From<String> for LinkStatus {
-    fn from(s: String) -> Self {
+impl TryFrom<String> for LinkStatus {
+    type Error = Report;
+
+    fn try_from(s: String) -> Result<Self> {
         match s.as_str() {
-            "pending" | "Pending" => LinkStatus::Pending,
-            "downloaded" | "Downloaded" => LinkStatus::Downloaded,
-            "error" | "Error" => LinkStatus::Error,
-            _ => panic!("Invalid link status: {}", s),
+            "pending" | "Pending" => Ok(LinkStatus::Pending),
+            "downloaded" | "Downloaded" => Ok(LinkStatus::Downloaded),
+            "error" | "Error" => Ok(LinkStatus::Error),
+            "skipped_too_large" | "SkippedTooLarge" => Ok(LinkStatus::SkippedTooLarge),
+            _ => bail!("invalid link status `{s}`"),
         }
     }
 }
 
-#[derive(Debug, Type, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash)]
+#[derive(Debug, Type, Clone, Copy, Serialize, Deserialize, PartialEq, Eq, Hash, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 pub enum PostType {
+    #[value(alias = "videos")]
     Video,
+    #[value(alias = "images")]
     Image,
 }
 
-impl From<String> for PostType {
-    fn from(s: String) -> Self {
+impl TryFrom<String> for PostType {
+    type Error = Report;
+
+    fn try_from(s: String) -> Result<Self> {
         match s.as_str() {
-            "Video" | "video" => PostType::Video,
-            "Image" | "image" => PostType::Image,
-            _ => panic!("Invalid post type: {}", s),
+            "Video" | "video" => Ok(PostType::Video),
+            "Image" | "image" => Ok(PostType::Image),
+            _ => bail!("invalid post type `{s}`"),
         }
     }
 }
@@ -86,10 +116,36 @@ pub struct CreatePost {
     pub id: i64,
     pub title: String,
     pub creator: String,
+    /// Normalized tags: without `#`, lowercased and without duplicates.
     pub tags: Vec<String>,
+    /// Tags as they were scraped.
+    pub raw_tags: Vec<String>,
+    /// Whether the creator pinned the post, so it shows up on every page.
+    pub pinned: bool,
     pub post_type: PostType,
     pub like_count: i64,
     pub links: Vec<CreatePostLink>,
+    pub source_page: Option<i64>,
+    pub body: Option<String>,
+}
+
+impl CreatePost {
+    /// The links without repeated URLs, keeping the first one. Galleries often reference an image
+    /// both in their JSON and in the HTML.
+    fn unique_links(&self) -> impl Iterator<Item = &CreatePostLink> {
+        let mut seen = HashSet::new();
+        self.links
+            .iter()
+            .filter(move |link| seen.insert(link.url.as_str()))
+    }
+}
+
+/// A comment on a post. Comments without a visible author have an empty one.
+#[derive(Debug, PartialEq)]
+pub struct CreateComment {
+    pub post_id: i64,
+    pub author: String,
+    pub text: String,
 }
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -97,12 +153,46 @@ pub struct Post {
     pub id: i64,
     pub title: String,
     pub creator: String,
+    /// Normalized tags: without `#`, lowercased and without duplicates.
     pub tags: Vec<String>,
+    /// Tags as they were scraped.
+    pub raw_tags: Vec<String>,
+    /// Whether the creator pinned the post, so it shows up on every page.
+    pub pinned: bool,
     pub post_type: PostType,
     pub like_count: i64,
     pub links: Vec<PostLink>,
     pub generated_title: Option<String>,
-    pub created_at: Option<NaiveDate>,
+    pub created_at: Option<DateTime<Utc>>,
+    pub source_page: Option<i64>,
+    pub body: Option<String>,
+    pub scraped_at: Option<DateTime<Utc>>,
+}
+
+impl From<&Post> for CreatePost {
+    fn from(post: &Post) -> Self {
+        CreatePost {
+            id: post.id,
+            title: post.title.clone(),
+            creator: post.creator.clone(),
+            tags: post.tags.clone(),
+            raw_tags: post.raw_tags.clone(),
+            pinned: post.pinned,
+            post_type: post.post_type,
+            like_count: post.like_count,
+            links: post
+                .links
+                .iter()
+                .map(|link| CreatePostLink {
+                    url: link.url.clone(),
+                    content_type: link.content_type.clone(),
+                    source: link.source,
+                })
+                .collect(),
+            source_page: post.source_page,
+            body: post.body.clone(),
+        }
+    }
 }
 
 #[derive(Debug)]
@@ -110,62 +200,320 @@ pub enum StatusUpdate {
     Success {
         file_path: String,
         file_path_pattern: String,
+        etag: Option<String>,
+        last_modified: Option<String>,
     },
     Error {
         error: String,
     },
+    SkippedTooLarge,
     Pending,
 }
 
+/// A window into a listing. Applied in SQL, so large archives aren't loaded all at once.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Page {
+    /// How many entries to return, all of them if `None`.
+    pub limit: Option<u32>,
+    /// How many entries to skip.
+    pub offset: u32,
+}
+
+impl Page {
+    /// The `LIMIT` value, where SQLite takes a negative limit as no limit.
+    fn limit(self) -> i64 {
+        self.limit.map_or(-1, i64::from)
+    }
+
+    fn offset(self) -> i64 {
+        i64::from(self.offset)
+    }
+}
+
+/// Aggregated counts for all posts of a single creator.
+#[derive(Debug)]
+pub struct CreatorSummary {
+    pub creator: String,
+    pub post_count: i64,
+    pub downloaded: i64,
+    pub pending: i64,
+    pub errors: i64,
+    pub first_date: Option<NaiveDate>,
+    pub last_date: Option<NaiveDate>,
+}
+
+/// How long a `metadata` or `download` run took and how many posts or links it processed.
+#[derive(Debug, PartialEq)]
+pub struct RunStats {
+    pub command: String,
+    pub started_at: DateTime<Utc>,
+    pub duration: Duration,
+    pub items: i64,
+}
+
+impl RunStats {
+    pub fn new(command: &str, started_at: DateTime<Utc>, duration: Duration, items: usize) -> Self {
+        Self {
+            command: command.to_string(),
+            started_at,
+            duration,
+            items: items as i64,
+        }
+    }
+
+    /// Processed items per minute, 0 for runs that took no measurable time.
+    pub fn per_minute(&self) -> f64 {
+        let seconds = self.duration.as_secs_f64();
+        if seconds > 0.0 {
+            self.items as f64 * 60.0 / seconds
+        } else {
+            0.0
+        }
+    }
+
+    /// E.g. `120 posts in 5 minutes (24.0 per minute)`.
+    pub fn describe(&self) -> String {
+        let unit = match self.command.as_str() {
+            "metadata" => "posts",
+            _ => "links",
+        };
+        format!(
+            "{} {unit} in {} ({:.1} per minute)",
+            self.items,
+            HumanDuration(self.duration),
+            self.per_minute()
+        )
+    }
+}
+
+/// The profile of a creator, as stored by `download-profile`.
+#[derive(Debug)]
+pub struct CreatorProfile {
+    pub name: String,
+    pub hutt_id: i64,
+    pub bio: Option<String>,
+    pub avatar_path: Option<String>,
+    pub banner_path: Option<String>,
+}
+
 struct JoinedPost {
     // Post fields
     pub id: i64,
     pub title: String,
     pub creator: String,
     pub tags: String,
-    pub post_type: PostType,
+    pub raw_tags: Option<String>,
+    pub pinned: bool,
+    pub post_type: String,
     pub like_count: i64,
     pub generated_title: Option<String>,
     pub created_at: Option<String>,
+    pub source_page: Option<i64>,
+    pub body: Option<String>,
+    pub scraped_at: Option<String>,
 
-    // PostLink fields
-    pub rowid: i64,
-    pub url: String,
-    pub content_type: String,
-    pub source: LinkSource,
-    pub status: LinkStatus,
+    // PostLink fields, all `None` for a post without links
+    pub rowid: Option<i64>,
+    pub url: Option<String>,
+    pub content_type: Option<String>,
+    pub source: Option<String>,
+    pub status: Option<String>,
     pub error: Option<String>,
     pub file_path: Option<String>,
     pub file_path_pattern: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+    pub original_name: Option<String>,
+    pub expected_size: Option<i64>,
 }
 
-fn to_hutt_post(posts: Vec<JoinedPost>) -> Post {
-    let first = &posts[0];
-    Post {
+/// Parses a stored post date, either a full RFC 3339 timestamp or a plain `%Y-%m-%d` date that is
+/// taken as midnight UTC.
+fn parse_created_at(value: &str) -> Option<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(value)
+        .map(|date| date.with_timezone(&Utc))
+        .ok()
+        .or_else(|| {
+            NaiveDate::parse_from_str(value, "%Y-%m-%d")
+                .ok()
+                .map(|date| date.and_time(NaiveTime::MIN).and_utc())
+        })
+}
+
+/// Turns the rows of one post into a `Post`, or `None` if there are no rows. A post type or tags
+/// that can't be parsed are an error, links with an unknown source or status are left out with a
+/// warning.
+fn to_hutt_post(posts: Vec<JoinedPost>) -> Result<Option<Post>> {
+    let Some(first) = posts.first() else {
+        return Ok(None);
+    };
+    let post_type = PostType::try_from(first.post_type.clone())
+        .wrap_err_with(|| format!("post {} can't be read", first.id))?;
+    let tags: Vec<String> = serde_json::from_str(&first.tags)
+        .wrap_err_with(|| format!("tags of post {} can't be read", first.id))?;
+    Ok(Some(Post {
         id: first.id,
         title: first.title.clone(),
         creator: first.creator.clone(),
-        tags: serde_json::from_str(&first.tags).unwrap(),
-        post_type: first.post_type,
+        tags: tags.clone(),
+        // posts scraped before raw tags were stored only have the normalized ones
+        raw_tags: first
+            .raw_tags
+            .as_deref()
+            .and_then(|raw_tags| serde_json::from_str(raw_tags).ok())
+            .unwrap_or(tags),
+        pinned: first.pinned,
+        post_type,
         like_count: first.like_count,
         generated_title: first.generated_title.clone(),
-        created_at: first
-            .created_at
-            .clone()
-            .and_then(|s| NaiveDate::parse_from_str(&s, "%Y-%m-%d").ok()),
+        created_at: first.created_at.as_deref().and_then(parse_created_at),
+        source_page: first.source_page,
+        body: first.body.clone(),
+        scraped_at: first
+            .scraped_at
+            .as_deref()
+            .and_then(|s| DateTime::parse_from_rfc3339(s).ok())
+            .map(|date| date.with_timezone(&Utc)),
         links: posts
             .into_iter()
-            .map(|post| PostLink {
-                id: post.rowid,
-                url: post.url,
-                content_type: post.content_type,
-                source: post.source,
-                status: post.status,
-                error: post.error,
-                file_path: post.file_path,
-                file_path_pattern: post.file_path_pattern,
+            .filter_map(|post| {
+                let (Some(id), Some(url), Some(content_type), Some(source), Some(status)) = (
+                    post.rowid,
+                    post.url,
+                    post.content_type,
+                    post.source,
+                    post.status,
+                ) else {
+                    return None;
+                };
+                // links stored before the original name was recorded
+                let original_name = post.original_name.or_else(|| url_basename(&url));
+                match (LinkSource::try_from(source), LinkStatus::try_from(status)) {
+                    (Ok(source), Ok(status)) => Some(PostLink {
+                        id,
+                        url,
+                        content_type,
+                        source,
+                        status,
+                        error: post.error,
+                        file_path: post.file_path,
+                        file_path_pattern: post.file_path_pattern,
+                        etag: post.etag,
+                        last_modified: post.last_modified,
+                        original_name,
+                        expected_size: post.expected_size,
+                    }),
+                    (Err(e), _) | (_, Err(e)) => {
+                        warn!("skipping link {id} of post {}: {e}", post.id);
+                        None
+                    }
+                }
             })
             .collect(),
+    }))
+}
+
+/// How aggressively SQLite syncs writes to disk, see `PRAGMA synchronous`.
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Synchronous {
+    Off,
+    #[default]
+    Normal,
+    Full,
+    Extra,
+}
+
+impl From<Synchronous> for SqliteSynchronous {
+    fn from(value: Synchronous) -> Self {
+        match value {
+            Synchronous::Off => SqliteSynchronous::Off,
+            Synchronous::Normal => SqliteSynchronous::Normal,
+            Synchronous::Full => SqliteSynchronous::Full,
+            Synchronous::Extra => SqliteSynchronous::Extra,
+        }
+    }
+}
+
+/// Opens the database in WAL mode, so that read-only commands can run alongside writes.
+fn connect_options(path: &Utf8Path, synchronous: Synchronous) -> SqliteConnectOptions {
+    SqliteConnectOptions::new()
+        .filename(path)
+        .journal_mode(SqliteJournalMode::Wal)
+        .synchronous(synchronous.into())
+        .busy_timeout(Duration::from_secs(5))
+}
+
+/// Opens an existing database. Creating and migrating it is up to `init`, so a missing or
+/// outdated database asks the user to run that first.
+pub async fn connect(path: &Utf8Path, synchronous: Synchronous) -> Result<SqlitePool> {
+    if !path.is_file() {
+        bail!("no database found at `{path}`, run `hutt-archiver init` first");
+    }
+    let pool = SqlitePool::connect_with(connect_options(path, synchronous)).await?;
+    if has_pending_migrations(&pool).await? {
+        bail!("the database `{path}` is out of date, run `hutt-archiver init` first to upgrade it");
+    }
+    Ok(pool)
+}
+
+async fn has_pending_migrations(pool: &SqlitePool) -> Result<bool> {
+    // the table doesn't exist before the first migration ran
+    let applied: HashSet<i64> =
+        sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = 1")
+            .fetch_all(pool)
+            .await
+            .unwrap_or_default()
+            .into_iter()
+            .collect();
+    let pending = sqlx::migrate!()
+        .iter()
+        .any(|migration| !applied.contains(&migration.version));
+    Ok(pending)
+}
+
+/// Creates the database if it doesn't exist yet and applies all pending migrations.
+pub async fn create(path: &Utf8Path) -> Result<SqlitePool> {
+    let options = connect_options(path, Synchronous::default()).create_if_missing(true);
+    let pool = SqlitePool::connect_with(options).await?;
+    sqlx::migrate!().run(&pool).await?;
+    Ok(pool)
+}
+
+/// Yields posts one at a time instead of loading the whole database into memory. The rows are
+/// ordered by post id, so a post is complete as soon as a row of the next post arrives.
+pub struct PostStream<'a> {
+    rows: BoxStream<'a, sqlx::Result<JoinedPost>>,
+    current: Vec<JoinedPost>,
+}
+
+impl PostStream<'_> {
+    /// The next post that can be read. Posts with invalid values are skipped with a warning, so
+    /// one bad row doesn't stop the whole run.
+    pub async fn next_post(&mut self) -> Result<Option<Post>> {
+        while let Some(rows) = self.next_rows().await? {
+            match to_hutt_post(rows) {
+                Ok(Some(post)) => return Ok(Some(post)),
+                Ok(None) => {}
+                Err(e) => warn!("skipping post: {e:#}"),
+            }
+        }
+        Ok(None)
+    }
+
+    async fn next_rows(&mut self) -> Result<Option<Vec<JoinedPost>>> {
+        while let Some(row) = self.rows.try_next().await? {
+            if self.current.last().is_some_and(|last| last.id != row.id) {
+                return Ok(Some(std::mem::replace(&mut self.current, vec![row])));
+            }
+            self.current.push(row);
+        }
+
+        if self.current.is_empty() {
+            Ok(None)
+        } else {
+            Ok(Some(std::mem::take(&mut self.current)))
+        }
     }
 }
 
@@ -178,48 +526,381 @@ impl Database {
         Self { db: pool }
     }
 
-    pub async fn insert_post(&self, post: &CreatePost) -> Result<()> {
+    async fn insert_post_with(connection: &mut SqliteConnection, post: &CreatePost) -> Result<()> {
         info!("Inserting post: {:#?}", post);
         let tags = serde_json::to_string(&post.tags)?;
+        let raw_tags = serde_json::to_string(&post.raw_tags)?;
+        let scraped_at = Utc::now().to_rfc3339();
+        sqlx::query!(
+            "
+            INSERT INTO posts (id, title, creator, tags, raw_tags, pinned, post_type, like_count, source_page, body, scraped_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+            post.id,
+            post.title,
+            post.creator,
+            tags,
+            raw_tags,
+            post.pinned,
+            post.post_type,
+            post.like_count,
+            post.source_page,
+            post.body,
+            scraped_at,
+        )
+        .execute(&mut *connection)
+        .await?;
+
+        for link in post.unique_links() {
+            let original_name = url_basename(&link.url);
+            sqlx::query!(
+                "
+                INSERT INTO post_links (url, content_type, source, post_id, status, original_name)
+                VALUES (?, ?, ?, ?, ?, ?)
+            ",
+                link.url,
+                link.content_type,
+                link.source,
+                post.id,
+                LinkStatus::Pending,
+                original_name,
+            )
+            .execute(&mut *connection)
+            .await?;
+        }
+
+        Ok(())
+    }
+
+    pub async fn insert_post(&self, post: &CreatePost) -> Result<()> {
+        let mut transaction = self.db.begin().await?;
+        Self::insert_post_with(&mut transaction, post).await?;
+        transaction.commit().await?;
+
+        Ok(())
+    }
+
+    /// Inserts all posts in a single transaction, skipping the ones that are stored already like
+    /// `insert_post_if_new`. If any of them fails, nothing is inserted. Returns how many posts
+    /// were new.
+    pub async fn insert_posts(&self, posts: &[CreatePost]) -> Result<usize> {
+        let mut transaction = self.db.begin().await?;
+        let mut added = 0;
+        for post in posts {
+            let (post_added, _) = Self::insert_post_if_new_with(&mut transaction, post).await?;
+            if post_added {
+                added += 1;
+            }
+        }
+        transaction.commit().await?;
+
+        Ok(added)
+    }
+
+    /// Inserts the post unless one with the same id exists, and adds any of its links that aren't
+    /// stored yet. Returns whether the post was new and how many links were added.
+    pub async fn insert_post_if_new(&self, post: &CreatePost) -> Result<(bool, u64)> {
         let mut transaction = self.db.begin().await?;
+        let result = Self::insert_post_if_new_with(&mut transaction, post).await?;
+        transaction.commit().await?;
+
+        Ok(result)
+    }
+
+    async fn insert_post_if_new_with(
+        connection: &mut SqliteConnection,
+        post: &CreatePost,
+    ) -> Result<(bool, u64)> {
+        let tags = serde_json::to_string(&post.tags)?;
+        let raw_tags = serde_json::to_string(&post.raw_tags)?;
+        let scraped_at = Utc::now().to_rfc3339();
+        let result = sqlx::query!(
+            "
+            INSERT OR IGNORE INTO posts (id, title, creator, tags, raw_tags, pinned, post_type, like_count, source_page, body, scraped_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+        ",
+            post.id,
+            post.title,
+            post.creator,
+            tags,
+            raw_tags,
+            post.pinned,
+            post.post_type,
+            post.like_count,
+            post.source_page,
+            post.body,
+            scraped_at,
+        )
+        .execute(&mut *connection)
+        .await?;
+        let post_added = result.rows_affected() > 0;
+        let links_added = Self::insert_new_links(connection, post).await?;
+
+        Ok((post_added, links_added))
+    }
+
+    /// Inserts the post, or updates the fields that can change after it was posted (title, body,
+    /// tags, likes and whether it's pinned) and when it was scraped if it exists. Links that aren't stored yet are added,
+    /// stored ones keep their status. Returns whether the post was new and how many links were
+    /// added.
+    pub async fn upsert_post(&self, post: &CreatePost) -> Result<(bool, u64)> {
+        let tags = serde_json::to_string(&post.tags)?;
+        let raw_tags = serde_json::to_string(&post.raw_tags)?;
+        let scraped_at = Utc::now().to_rfc3339();
+        let mut transaction = self.db.begin().await?;
+        let existing = sqlx::query_scalar!(
+            r#"SELECT COUNT(*) AS "count!: i64" FROM posts WHERE id = ?"#,
+            post.id
+        )
+        .fetch_one(&mut *transaction)
+        .await?;
         sqlx::query!(
             "
-            INSERT INTO posts (id, title, creator, tags, post_type, like_count)
-            VALUES (?, ?, ?, ?, ?, ?)
+            INSERT INTO posts (id, title, creator, tags, raw_tags, pinned, post_type, like_count, source_page, body, scraped_at)
+            VALUES (?, ?, ?, ?, ?, ?, ?, ?, ?, ?, ?)
+            ON CONFLICT (id) DO UPDATE SET
+                title = excluded.title,
+                tags = excluded.tags,
+                raw_tags = excluded.raw_tags,
+                pinned = excluded.pinned,
+                like_count = excluded.like_count,
+                body = excluded.body,
+                scraped_at = excluded.scraped_at
         ",
             post.id,
             post.title,
             post.creator,
             tags,
+            raw_tags,
+            post.pinned,
             post.post_type,
             post.like_count,
+            post.source_page,
+            post.body,
+            scraped_at,
         )
         .execute(&mut *transaction)
         .await?;
+        let links_added = Self::insert_new_links(&mut transaction, post).await?;
+        transaction.commit().await?;
 
-        for link in &post.links {
-            sqlx::query!(
+        Ok((existing == 0, links_added))
+    }
+
+    /// Adds the links of the post whose URL isn't stored yet and returns how many were added.
+    async fn insert_new_links(connection: &mut SqliteConnection, post: &CreatePost) -> Result<u64> {
+        // links stored before URLs were normalized only match the scraped ones when normalized
+        let stored = sqlx::query_scalar!("SELECT url FROM post_links WHERE post_id = ?", post.id)
+            .fetch_all(&mut *connection)
+            .await?;
+        let stored: HashSet<String> = stored.iter().map(|url| normalize_url(url)).collect();
+
+        let mut links_added = 0;
+        for link in post.unique_links() {
+            if stored.contains(&normalize_url(&link.url)) {
+                continue;
+            }
+            let original_name = url_basename(&link.url);
+            let result = sqlx::query!(
                 "
-                INSERT INTO post_links (url, content_type, source, post_id, status)
-                VALUES (?, ?, ?, ?, ?)
+                INSERT OR IGNORE INTO post_links (url, content_type, source, post_id, status, original_name)
+                VALUES (?, ?, ?, ?, ?, ?)
             ",
                 link.url,
                 link.content_type,
                 link.source,
                 post.id,
                 LinkStatus::Pending,
+                original_name,
             )
-            .execute(&mut *transaction)
+            .execute(&mut *connection)
             .await?;
+            links_added += result.rows_affected();
         }
 
+        Ok(links_added)
+    }
+
+    /// Stores the comments of posts that are in the database, ignoring ones that were stored
+    /// before. Returns how many were added.
+    pub async fn insert_comments(&self, comments: &[CreateComment]) -> Result<u64> {
+        let mut transaction = self.db.begin().await?;
+        let mut added = 0;
+        for comment in comments {
+            let result = sqlx::query!(
+                "
+                INSERT OR IGNORE INTO comments (post_id, author, text)
+                SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM posts WHERE id = ?1)
+            ",
+                comment.post_id,
+                comment.author,
+                comment.text,
+            )
+            .execute(&mut *transaction)
+            .await?;
+            added += result.rows_affected();
+        }
         transaction.commit().await?;
 
+        Ok(added)
+    }
+
+    pub async fn insert_run(&self, run: &RunStats) -> Result<()> {
+        let started_at = run.started_at.to_rfc3339();
+        let duration_seconds = run.duration.as_secs_f64();
+        sqlx::query!(
+            "INSERT INTO runs (command, started_at, duration_seconds, items) VALUES (?, ?, ?, ?)",
+            run.command,
+            started_at,
+            duration_seconds,
+            run.items,
+        )
+        .execute(&self.db)
+        .await?;
         Ok(())
     }
 
-    pub async fn set_post_date(&self, post_id: i64, date: NaiveDate) -> Result<()> {
-        let date = date.format("%Y-%m-%d").to_string();
+    /// The most recent runs, newest first.
+    pub async fn recent_runs(&self, limit: u32) -> Result<Vec<RunStats>> {
+        let rows = sqlx::query!(
+            "SELECT command, started_at, duration_seconds, items FROM runs
+            ORDER BY started_at DESC, rowid DESC LIMIT ?",
+            limit
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(rows
+            .into_iter()
+            .filter_map(|row| {
+                let started_at = DateTime::parse_from_rfc3339(&row.started_at).ok()?;
+                Some(RunStats {
+                    command: row.command,
+                    started_at: started_at.with_timezone(&Utc),
+                    duration: Duration::from_secs_f64(row.duration_seconds.max(0.0)),
+                    items: row.items,
+                })
+            })
+            .collect())
+    }
+
+    /// Which of the given post ids are already in the database.
+    pub async fn existing_post_ids(&self, ids: &[i64]) -> Result<HashSet<i64>> {
+        let ids = serde_json::to_string(ids)?;
+        let existing = sqlx::query_scalar!(
+            "SELECT id FROM posts WHERE id IN (SELECT value FROM json_each(?))",
+            ids
+        )
+        .fetch_all(&self.db)
+        .await?;
+        Ok(existing.into_iter().collect())
+    }
+
+    pub async fn applied_migrations(&self) -> Result<Vec<i64>> {
+        let versions: Vec<i64> =
+            sqlx::query_scalar("SELECT version FROM _sqlx_migrations WHERE success = TRUE")
+                .fetch_all(&self.db)
+                .await?;
+        Ok(versions)
+    }
+
+    pub async fn insert_scrape_error(
+        &self,
+        post_id: Option<&str>,
+        page: i64,
+        reason: &str,
+    ) -> Result<()> {
+        sqlx::query!(
+            "INSERT INTO scrape_errors (post_id, source_page, reason) VALUES (?, ?, ?)",
+            post_id,
+            page,
+            reason
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Stores a creator's profile, replacing the one saved before.
+    pub async fn upsert_creator_profile(&self, profile: &CreatorProfile) -> Result<()> {
+        let updated_at = Utc::now().to_rfc3339();
+        sqlx::query!(
+            "
+            INSERT INTO creators (name, hutt_id, bio, avatar_path, banner_path, updated_at)
+            VALUES (?, ?, ?, ?, ?, ?)
+            ON CONFLICT (name) DO UPDATE SET
+                hutt_id = excluded.hutt_id,
+                bio = excluded.bio,
+                avatar_path = excluded.avatar_path,
+                banner_path = excluded.banner_path,
+                updated_at = excluded.updated_at
+        ",
+            profile.name,
+            profile.hutt_id,
+            profile.bio,
+            profile.avatar_path,
+            profile.banner_path,
+            updated_at,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    /// Counts the posts that were scraped without any downloadable links and were never stored.
+    pub async fn count_posts_without_links(&self) -> Result<i64> {
+        let count = sqlx::query_scalar!(
+            "SELECT COUNT(DISTINCT post_id) AS count FROM scrape_errors
+            WHERE reason = 'no links' AND post_id NOT IN (SELECT 'post-' || id FROM posts)"
+        )
+        .fetch_one(&self.db)
+        .await?;
+        Ok(count)
+    }
+
+    pub async fn creator_summaries(&self, page: Page) -> Result<Vec<CreatorSummary>> {
+        let limit = page.limit();
+        let offset = page.offset();
+        let rows = sqlx::query!(
+            r#"
+            SELECT p.creator AS creator,
+                COUNT(DISTINCT p.id) AS "post_count!: i64",
+                COALESCE(SUM(pl.status = 'downloaded'), 0) AS "downloaded!: i64",
+                COALESCE(SUM(pl.status = 'pending'), 0) AS "pending!: i64",
+                COALESCE(SUM(pl.status = 'error'), 0) AS "errors!: i64",
+                MIN(p.created_at) AS first_date,
+                MAX(p.created_at) AS last_date
+            FROM posts p LEFT JOIN post_links pl ON p.id = pl.post_id
+            GROUP BY p.creator
+            ORDER BY p.creator
+            LIMIT ? OFFSET ?
+        "#,
+            limit,
+            offset,
+        )
+        .fetch_all(&self.db)
+        .await?;
+
+        let parse_date = |date: Option<String>| {
+            date.as_deref()
+                .and_then(parse_created_at)
+                .map(|date| date.date_naive())
+        };
+        Ok(rows
+            .into_iter()
+            .map(|row| CreatorSummary {
+                creator: row.creator,
+                post_count: row.post_count,
+                downloaded: row.downloaded,
+                pending: row.pending,
+                errors: row.errors,
+                first_date: parse_date(row.first_date),
+                last_date: parse_date(row.last_date),
+            })
+            .collect())
+    }
+
+    pub async fn set_post_date(&self, post_id: i64, date: DateTime<Utc>) -> Result<()> {
+        let date = date.to_rfc3339();
 
         sqlx::query!(
             "UPDATE posts SET created_at = ? WHERE id = ?",
@@ -231,26 +912,40 @@ impl Database {
         Ok(())
     }
 
-    pub async fn fetch_by_id(&self, id: i64) -> Result<Post> {
+    /// The post with the given id, or `None` if there is no such post.
+    pub async fn fetch_by_id(&self, id: i64) -> Result<Option<Post>> {
         let post = sqlx::query_as!(
             JoinedPost,
-            "SELECT p.id, p.title, p.creator, p.tags, p.post_type, p.like_count, p.generated_title, p.created_at,
-                   pl.rowid, pl.url, pl.content_type, pl.source, pl.status, pl.error, pl.file_path, pl.file_path_pattern
+            "SELECT p.id, p.title, p.creator, p.tags, p.raw_tags, p.pinned, p.post_type, p.like_count, p.generated_title, p.created_at, p.source_page, p.body, p.scraped_at,
+                   pl.rowid, pl.url, pl.content_type, pl.source, pl.status, pl.error, pl.file_path, pl.file_path_pattern, pl.etag, pl.last_modified, pl.original_name, pl.expected_size
             FROM posts p
-            INNER JOIN post_links pl ON p.id = pl.post_id 
-            WHERE id = ?",
+            LEFT JOIN post_links pl ON p.id = pl.post_id
+            WHERE p.id = ?",
             id
         )
         .fetch_all(&self.db)
         .await?;
-        Ok(to_hutt_post(post))
+        to_hutt_post(post)
     }
 
-    pub async fn reset_downloads(&self) -> Result<()> {
-        sqlx::query!("UPDATE post_links SET status = 'pending', error = NULL, file_path = NULL, file_path_pattern = NULL")
-            .execute(&self.db)
-            .await?;
-        Ok(())
+    /// Resets links to `Pending`, optionally only for posts of one creator and/or post type.
+    /// Returns the number of links that were reset.
+    pub async fn reset_downloads(
+        &self,
+        creator: Option<&str>,
+        post_type: Option<PostType>,
+    ) -> Result<u64> {
+        let result = sqlx::query!(
+            "UPDATE post_links SET status = 'pending', error = NULL, file_path = NULL, file_path_pattern = NULL
+            WHERE post_id IN (
+                SELECT id FROM posts WHERE (?1 IS NULL OR creator = ?1) AND (?2 IS NULL OR post_type = ?2)
+            )",
+            creator,
+            post_type,
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(result.rows_affected())
     }
 
     pub async fn update_path(&self, link_id: i64, file_path: &str, pattern: &str) -> Result<()> {
@@ -265,6 +960,29 @@ impl Database {
         Ok(())
     }
 
+    /// Remembers the size of a link's file so later runs don't have to ask the server again.
+    pub async fn set_expected_size(&self, link_id: i64, size: i64) -> Result<()> {
+        sqlx::query!(
+            "UPDATE post_links SET expected_size = ? WHERE rowid = ?",
+            size,
+            link_id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
+    pub async fn update_content_type(&self, link_id: i64, content_type: &str) -> Result<()> {
+        sqlx::query!(
+            "UPDATE post_links SET content_type = ? WHERE rowid = ?",
+            content_type,
+            link_id
+        )
+        .execute(&self.db)
+        .await?;
+        Ok(())
+    }
+
     pub async fn set_generated_title(&self, post_id: i64, title: &str) -> Result<()> {
         sqlx::query!(
             "UPDATE posts SET generated_title = ? WHERE id = ?",
@@ -276,30 +994,61 @@ impl Database {
         Ok(())
     }
 
-    pub async fn fetch_all(&self) -> Result<Vec<Post>> {
-        use itertools::Itertools;
-
-        let posts = sqlx::query_as!(
+    pub fn stream_all(&self) -> PostStream<'_> {
+        let rows = sqlx::query_as!(
             JoinedPost,
-            "SELECT p.id, p.title, p.creator, p.tags, p.post_type, p.like_count, p.generated_title, p.created_at,
-                   pl.rowid, pl.url, pl.content_type, pl.source, pl.status, pl.error, pl.file_path, pl.file_path_pattern
-            FROM posts p INNER JOIN post_links pl ON p.id = pl.post_id
+            "SELECT p.id, p.title, p.creator, p.tags, p.raw_tags, p.pinned, p.post_type, p.like_count, p.generated_title, p.created_at, p.source_page, p.body, p.scraped_at,
+                   pl.rowid, pl.url, pl.content_type, pl.source, pl.status, pl.error, pl.file_path, pl.file_path_pattern, pl.etag, pl.last_modified, pl.original_name, pl.expected_size
+            FROM posts p LEFT JOIN post_links pl ON p.id = pl.post_id
             ORDER BY p.id ASC"
         )
+        .fetch(&self.db);
+
+        PostStream {
+            rows,
+            current: Vec::new(),
+        }
+    }
+
+    /// Streams the posts that have links which failed to download, ordered by id.
+    pub fn stream_errors(&self, page: Page) -> PostStream<'_> {
+        let (limit, offset) = (page.limit(), page.offset());
+        // a streaming query borrows its arguments, which don't outlive this function, so the page
+        // is fetched at once
+        let rows = stream::once(async move {
+            sqlx::query_as!(
+            JoinedPost,
+            "SELECT p.id, p.title, p.creator, p.tags, p.raw_tags, p.pinned, p.post_type, p.like_count, p.generated_title, p.created_at, p.source_page, p.body, p.scraped_at,
+                   pl.rowid, pl.url, pl.content_type, pl.source, pl.status, pl.error, pl.file_path, pl.file_path_pattern, pl.etag, pl.last_modified, pl.original_name, pl.expected_size
+            FROM posts p LEFT JOIN post_links pl ON p.id = pl.post_id
+            WHERE p.id IN (
+                SELECT post_id FROM post_links WHERE status = 'error'
+                GROUP BY post_id ORDER BY post_id LIMIT ? OFFSET ?
+            )
+            ORDER BY p.id ASC",
+            limit,
+            offset,
+        )
         .fetch_all(&self.db)
-        .await?;
+        .await
+        })
+        .map_ok(|rows| stream::iter(rows.into_iter().map(Ok)))
+        .try_flatten()
+        .boxed();
 
-        let groups: BTreeMap<i64, Vec<JoinedPost>> = posts
-            .into_iter()
-            .chunk_by(|post| post.id)
-            .into_iter()
-            .map(|(id, group)| (id, group.collect_vec()))
-            .collect();
+        PostStream {
+            rows,
+            current: Vec::new(),
+        }
+    }
 
-        Ok(groups
-            .into_iter()
-            .map(|(_, posts)| to_hutt_post(posts))
-            .collect())
+    pub async fn fetch_all(&self) -> Result<Vec<Post>> {
+        let mut stream = self.stream_all();
+        let mut posts = Vec::new();
+        while let Some(post) = stream.next_post().await? {
+            posts.push(post);
+        }
+        Ok(posts)
     }
 
     pub async fn update_status(&self, link_id: i64, status_update: StatusUpdate) -> Result<()> {
@@ -307,11 +1056,17 @@ impl Database {
             StatusUpdate::Success {
                 file_path,
                 file_path_pattern,
+                etag,
+                last_modified,
             } => {
                 sqlx::query!(
-                    "UPDATE post_links SET status = 'downloaded', file_path = ?, file_path_pattern = ? WHERE rowid = ?",
+                    "UPDATE post_links SET status = 'downloaded', file_path = ?, file_path_pattern = ?,
+                        etag = COALESCE(?, etag), last_modified = COALESCE(?, last_modified)
+                    WHERE rowid = ?",
                     file_path,
                     file_path_pattern,
+                    etag,
+                    last_modified,
                     link_id,
                 )
                 .execute(&self.db)
@@ -326,6 +1081,14 @@ impl Database {
                 .execute(&self.db)
                 .await?;
             }
+            StatusUpdate::SkippedTooLarge => {
+                sqlx::query!(
+                    "UPDATE post_links SET status = 'skipped_too_large' WHERE rowid = ?",
+                    link_id
+                )
+                .execute(&self.db)
+                .await?;
+            }
             StatusUpdate::Pending => {
                 sqlx::query!(
                     "UPDATE post_links SET status = 'pending' WHERE rowid = ?",
@@ -341,6 +1104,11 @@ impl Database {
 
 #[cfg(test)]
 mod tests {
+    use std::collections::HashSet;
+    use std::sync::atomic::{AtomicU32, Ordering};
+    use std::time::Duration;
+
+    use chrono::{TimeZone, Utc};
     use color_eyre::Result;
     use fake::faker::lorem::en::{Sentence, Words};
     use fake::faker::name::en::Name;
@@ -349,7 +1117,10 @@ mod tests {
     use rand::Rng;
     use sqlx::SqlitePool;
 
-    use super::{CreatePost, CreatePostLink, LinkSource, PostType};
+    use super::{
+        parse_created_at, CreateComment, CreatePost, CreatePostLink, LinkSource, LinkStatus, Page,
+        PostType, RunStats, StatusUpdate,
+    };
     use crate::database::Database;
 
     fn random_link_source() -> LinkSource {
@@ -360,24 +1131,30 @@ mod tests {
             LinkSource::VideoPost,
         ]
         .choose(&mut rng)
+        .copied()
         .unwrap()
-        .clone()
     }
 
     fn random_post_type() -> PostType {
         let mut rng = rand::thread_rng();
         [PostType::Image, PostType::Video]
             .choose(&mut rng)
+            .copied()
             .unwrap()
-            .clone()
     }
 
     fn random_links(min: u32, max: u32) -> Vec<CreatePostLink> {
+        // link URLs are unique across posts, so they're numbered to keep every post's links
+        static NEXT_IMAGE: AtomicU32 = AtomicU32::new(1000);
+
         let mut rng = rand::thread_rng();
         let count = rng.gen_range(min..max);
         (0..count)
             .map(|_| CreatePostLink {
-                url: format!("https://hutt.co/images/{}/big", rng.gen_range(1000..9999)),
+                url: format!(
+                    "https://hutt.co/images/{}/big",
+                    NEXT_IMAGE.fetch_add(1, Ordering::Relaxed)
+                ),
                 content_type: ["image/jpeg", "image/png", "video/mp4"]
                     .choose(&mut rng)
                     .unwrap()
@@ -394,31 +1171,80 @@ mod tests {
             id: (0..10_000).fake(),
             title: Sentence(5..10).fake(),
             creator: Name().fake(),
+            raw_tags: tags.clone(),
             tags,
+            pinned: false,
             links: random_links(1, 10),
             post_type: random_post_type(),
             like_count: (0..250).fake(),
+            source_page: Some((0..100).fake()),
+            body: Some(Sentence(5..20).fake()),
         }
     }
 
+    #[sqlx::test(migrations = false)]
+    async fn test_pending_migrations(pool: SqlitePool) -> Result<()> {
+        assert!(super::has_pending_migrations(&pool).await?);
+        sqlx::migrate!().run(&pool).await?;
+        assert!(!super::has_pending_migrations(&pool).await?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_created_at() {
+        let date = parse_created_at("2024-05-12").unwrap();
+        assert_eq!(date.to_rfc3339(), "2024-05-12T00:00:00+00:00");
+
+        let date = parse_created_at("2024-05-12T18:30:00+02:00").unwrap();
+        assert_eq!(date.to_rfc3339(), "2024-05-12T16:30:00+00:00");
+
+        assert!(parse_created_at("yesterday").is_none());
+    }
+
     #[sqlx::test]
     async fn test_insert_post(pool: SqlitePool) -> Result<()> {
         let database = Database::new(pool);
         let post = random_post();
         database.insert_post(&post).await?;
 
-        let result = database.fetch_by_id(post.id).await?;
+        let result = database.fetch_by_id(post.id).await?.unwrap();
         assert_eq!(result.id, post.id);
 
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_insert_post_if_new(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let mut post = random_post();
+        let unique_urls: HashSet<_> = post.links.iter().map(|l| l.url.clone()).collect();
+        let (added, links_added) = database.insert_post_if_new(&post).await?;
+        assert!(added);
+        assert_eq!(links_added, unique_urls.len() as u64);
+
+        post.links.push(CreatePostLink {
+            url: "https://hutt.co/images/new/big".to_string(),
+            content_type: "image/jpeg".to_string(),
+            source: LinkSource::ImageGallery,
+        });
+        let (added, links_added) = database.insert_post_if_new(&post).await?;
+        assert!(!added);
+        assert_eq!(links_added, 1);
+
+        let result = database.fetch_by_id(post.id).await?.unwrap();
+        assert_eq!(result.links.len(), unique_urls.len() + 1);
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn test_list_posts(pool: SqlitePool) -> Result<()> {
         let database = Database::new(pool);
         let mut expected = (0..10).map(|_| random_post()).collect::<Vec<_>>();
 
         expected.sort_by_key(|p| p.id);
+        expected.dedup_by_key(|p| p.id);
         for post in &expected {
             database.insert_post(post).await?;
         }
@@ -429,22 +1255,382 @@ mod tests {
         Ok(())
     }
 
+    #[sqlx::test]
+    async fn test_insert_posts(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let mut posts = (0..5).map(|_| random_post()).collect::<Vec<_>>();
+        posts.sort_by_key(|p| p.id);
+        posts.dedup_by_key(|p| p.id);
+        assert_eq!(database.insert_posts(&posts[..3]).await?, 3);
+        // posts that are stored already are skipped instead of failing the batch
+        assert_eq!(database.insert_posts(&posts).await?, posts.len() - 3);
+
+        let result = database.fetch_all().await?;
+        assert_eq!(result.len(), posts.len());
+
+        Ok(())
+    }
+
     #[sqlx::test]
     async fn test_set_file_path(pool: SqlitePool) -> Result<()> {
         let database = Database::new(pool);
         let post = random_post();
         database.insert_post(&post).await?;
-        let post = database.fetch_by_id(post.id).await?;
+        let post = database.fetch_by_id(post.id).await?.unwrap();
 
         let link = post.links.first().unwrap();
         let new_path = format!("/tmp/{}", link.url);
         database.update_path(link.id, &new_path, "test").await?;
 
-        let result = database.fetch_by_id(post.id).await?;
+        let result = database.fetch_by_id(post.id).await?.unwrap();
         let updated_link = result.links.first().unwrap();
         assert_eq!(updated_link.file_path, Some(new_path));
         assert_eq!(updated_link.file_path_pattern, Some("test".to_string()));
 
         Ok(())
     }
+
+    #[test]
+    fn test_parse_unknown_values() {
+        assert_eq!(
+            PostType::try_from("image".to_string()).unwrap(),
+            PostType::Image
+        );
+        assert!(PostType::try_from("gif".to_string()).is_err());
+        assert!(LinkStatus::try_from("queued".to_string()).is_err());
+        assert!(LinkSource::try_from("embed".to_string()).is_err());
+    }
+
+    #[sqlx::test]
+    async fn test_skip_invalid_rows(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool.clone());
+        let posts: Vec<_> = (1..=3)
+            .map(|id| CreatePost {
+                id,
+                ..random_post()
+            })
+            .collect();
+        database.insert_posts(&posts).await?;
+
+        let broken_post = posts[0].id;
+        sqlx::query("UPDATE posts SET post_type = 'gif' WHERE id = ?")
+            .bind(broken_post)
+            .execute(&pool)
+            .await?;
+        let broken_tags = posts[2].id;
+        sqlx::query("UPDATE posts SET tags = 'not json' WHERE id = ?")
+            .bind(broken_tags)
+            .execute(&pool)
+            .await?;
+        let broken_link = database.fetch_by_id(posts[1].id).await?.unwrap().links[0].id;
+        sqlx::query("UPDATE post_links SET status = 'queued' WHERE rowid = ?")
+            .bind(broken_link)
+            .execute(&pool)
+            .await?;
+
+        assert!(database.fetch_by_id(broken_post).await.is_err());
+        assert!(database.fetch_by_id(broken_tags).await.is_err());
+        let result = database.fetch_all().await?;
+        assert_eq!(result.len(), posts.len() - 2);
+        assert!(result
+            .iter()
+            .all(|post| post.id != broken_post && post.id != broken_tags));
+        let post = result.iter().find(|post| post.id == posts[1].id).unwrap();
+        assert_eq!(post.links.len(), posts[1].links.len() - 1);
+        assert!(post.links.iter().all(|link| link.id != broken_link));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_post_without_links(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let post = CreatePost {
+            links: vec![],
+            ..random_post()
+        };
+        database.insert_post(&post).await?;
+
+        let result = database.fetch_by_id(post.id).await?.unwrap();
+        assert!(result.links.is_empty());
+        let all = database.fetch_all().await?;
+        assert_eq!(all.len(), 1);
+        assert!(super::to_hutt_post(vec![])?.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_fetch_missing_post(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        database.insert_post(&random_post()).await?;
+
+        assert!(database.fetch_by_id(-1).await?.is_none());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_recent_runs(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let started_at = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+        for (hour, items) in [(0, 10), (2, 30), (1, 20)] {
+            let run = RunStats::new(
+                "download",
+                started_at + chrono::Duration::hours(hour),
+                Duration::from_secs(120),
+                items,
+            );
+            database.insert_run(&run).await?;
+        }
+
+        let runs = database.recent_runs(2).await?;
+        let items: Vec<_> = runs.iter().map(|run| run.items).collect();
+        assert_eq!(items, vec![30, 20]);
+        assert_eq!(runs[0].duration, Duration::from_secs(120));
+        assert_eq!(runs[0].per_minute(), 15.0);
+        assert_eq!(
+            runs[0].describe(),
+            "30 links in 2 minutes (15.0 per minute)"
+        );
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_expected_size(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let post = random_post();
+        database.insert_post(&post).await?;
+        let link_id = database.fetch_by_id(post.id).await?.unwrap().links[0].id;
+        database.set_expected_size(link_id, 1024).await?;
+
+        let post = database.fetch_by_id(post.id).await?.unwrap();
+        let link = post.links.iter().find(|link| link.id == link_id).unwrap();
+        assert_eq!(link.expected_size, Some(1024));
+        assert!(post
+            .links
+            .iter()
+            .filter(|link| link.id != link_id)
+            .all(|link| link.expected_size.is_none()));
+
+        Ok(())
+    }
+
+    async fn error_ids(database: &Database, page: Page) -> Result<Vec<i64>> {
+        let mut stream = database.stream_errors(page);
+        let mut ids = vec![];
+        while let Some(post) = stream.next_post().await? {
+            ids.push(post.id);
+        }
+        Ok(ids)
+    }
+
+    #[sqlx::test]
+    async fn test_creator_summaries(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let post = CreatePost {
+            creator: "someone".into(),
+            links: random_links(2, 3),
+            ..random_post()
+        };
+        database.insert_post(&post).await?;
+        let link_id = database.fetch_by_id(post.id).await?.unwrap().links[0].id;
+        database
+            .update_status(
+                link_id,
+                StatusUpdate::Error {
+                    error: "failed".into(),
+                },
+            )
+            .await?;
+
+        let summaries = database.creator_summaries(Page::default()).await?;
+        assert_eq!(summaries.len(), 1);
+        let summary = &summaries[0];
+        assert_eq!(summary.creator, "someone");
+        assert_eq!(summary.post_count, 1);
+        assert_eq!((summary.pending, summary.errors), (1, 1));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_link_status_lowercase(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool.clone());
+        database.insert_post(&random_post()).await?;
+
+        let statuses: Vec<String> = sqlx::query_scalar("SELECT DISTINCT status FROM post_links")
+            .fetch_all(&pool)
+            .await?;
+        assert_eq!(statuses, vec!["pending"]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_stream_errors_paged(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        for id in 1..=5 {
+            database
+                .insert_post(&CreatePost {
+                    id,
+                    ..random_post()
+                })
+                .await?;
+        }
+        for id in [2, 3, 5] {
+            let link = &database.fetch_by_id(id).await?.unwrap().links[0];
+            database
+                .update_status(
+                    link.id,
+                    StatusUpdate::Error {
+                        error: "failed".into(),
+                    },
+                )
+                .await?;
+        }
+
+        assert_eq!(error_ids(&database, Page::default()).await?, vec![2, 3, 5]);
+        let page = Page {
+            limit: Some(2),
+            offset: 1,
+        };
+        assert_eq!(error_ids(&database, page).await?, vec![3, 5]);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_insert_duplicate_links(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let link = |content_type: &str| CreatePostLink {
+            url: "https://hutt.co/images/1234/big".to_string(),
+            content_type: content_type.to_string(),
+            source: LinkSource::ImageGallery,
+        };
+        let post = CreatePost {
+            links: vec![link("image/jpeg"), link("image/png")],
+            ..random_post()
+        };
+        database.insert_post(&post).await?;
+
+        let result = database.fetch_by_id(post.id).await?.unwrap();
+        assert_eq!(result.links.len(), 1);
+        assert_eq!(result.links[0].content_type, "image/jpeg");
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_upsert_post(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        let link = |url: &str| CreatePostLink {
+            url: url.to_string(),
+            content_type: "image/jpeg".to_string(),
+            source: LinkSource::ImageGallery,
+        };
+        let mut post = CreatePost {
+            links: vec![link("https://hutt.co/images/1/big")],
+            like_count: 1,
+            ..random_post()
+        };
+        assert_eq!(database.upsert_post(&post).await?, (true, 1));
+        let link_id = database.fetch_by_id(post.id).await?.unwrap().links[0].id;
+        database
+            .update_status(
+                link_id,
+                StatusUpdate::Success {
+                    file_path: "1.jpeg".to_string(),
+                    file_path_pattern: "{link_id}".to_string(),
+                    etag: None,
+                    last_modified: None,
+                },
+            )
+            .await?;
+
+        post.title = "edited".to_string();
+        post.like_count = 10;
+        post.links.push(link("https://hutt.co/images/2/big"));
+        assert_eq!(database.upsert_post(&post).await?, (false, 1));
+
+        let stored = database.fetch_by_id(post.id).await?.unwrap();
+        assert_eq!(stored.title, "edited");
+        assert_eq!(stored.like_count, 10);
+        assert_eq!(stored.links.len(), 2);
+        let first = stored.links.iter().find(|link| link.id == link_id).unwrap();
+        // links that were stored before keep their status
+        assert_eq!(first.status, LinkStatus::Downloaded);
+
+        // the stored URL isn't normalized, but it's still the same link
+        post.links = vec![link("/images/1/big")];
+        assert_eq!(database.upsert_post(&post).await?, (false, 0));
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_upsert_post_scraped_at(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool.clone());
+        let post = random_post();
+        database.insert_post(&post).await?;
+        let scraped_before = Utc.with_ymd_and_hms(2020, 1, 1, 0, 0, 0).unwrap();
+        sqlx::query("UPDATE posts SET scraped_at = ? WHERE id = ?")
+            .bind(scraped_before.to_rfc3339())
+            .bind(post.id)
+            .execute(&pool)
+            .await?;
+
+        database.upsert_post(&post).await?;
+        let stored = database.fetch_by_id(post.id).await?.unwrap();
+        assert!(stored.scraped_at.unwrap() > scraped_before);
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_existing_post_ids(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        for id in [1, 2] {
+            database
+                .insert_post(&CreatePost {
+                    id,
+                    ..random_post()
+                })
+                .await?;
+        }
+
+        let existing = database.existing_post_ids(&[1, 2, 3]).await?;
+        assert_eq!(existing, HashSet::from([1, 2]));
+        assert!(database.existing_post_ids(&[]).await?.is_empty());
+
+        Ok(())
+    }
+
+    #[sqlx::test]
+    async fn test_insert_comments(pool: SqlitePool) -> Result<()> {
+        let database = Database::new(pool);
+        database
+            .insert_post(&CreatePost {
+                id: 1,
+                ..random_post()
+            })
+            .await?;
+        let comment = |post_id, text: &str| CreateComment {
+            post_id,
+            author: "someone".into(),
+            text: text.into(),
+        };
+        let comments = [
+            comment(1, "first"),
+            comment(1, "second"),
+            comment(2, "orphan"),
+        ];
+
+        assert_eq!(database.insert_comments(&comments).await?, 2);
+        // comments that were stored before are ignored
+        assert_eq!(database.insert_comments(&comments).await?, 0);
+
+        Ok(())
+    }
 }
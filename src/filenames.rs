@@ -1,6 +1,59 @@
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
 use camino::{Utf8Path, Utf8PathBuf};
+use clap::ValueEnum;
+use color_eyre::eyre::bail;
+use regex::{Captures, Regex};
+use reqwest::Url;
 
 use crate::database::{Post, PostType};
+use crate::Result;
+
+/// Placeholders that can be used in a filename pattern.
+pub const PLACEHOLDERS: &[&str] = &[
+    "{post_id}",
+    "{title}",
+    "{link_id}",
+    "{type}",
+    "{type_lower}",
+    "{likes}",
+    "{original_name}",
+    "{index}",
+];
+
+/// The image pattern of `download --flatten`: all images in one directory, numbered per post.
+pub const FLAT_IMAGE_PATTERN: &str = "{type}/{post_id} - {title} - {index}";
+
+/// `{likes}`, optionally zero-padded to a width like `{likes:05}` so names sort by popularity.
+static LIKES: LazyLock<Regex> = LazyLock::new(|| Regex::new(r"\{likes(?::0(\d+))?\}").unwrap());
+
+/// Checks that a filename pattern only uses known placeholders.
+pub fn validate_pattern(pattern: &str) -> Result<()> {
+    if pattern.trim().is_empty() {
+        bail!("pattern must not be empty");
+    }
+
+    let mut rest = pattern;
+    while let Some(start) = rest.find('{') {
+        let Some(end) = rest[start..].find('}') else {
+            bail!("unclosed `{{` in pattern `{pattern}`");
+        };
+        let placeholder = &rest[start..start + end + 1];
+        let is_likes = LIKES
+            .find(placeholder)
+            .is_some_and(|likes| likes.as_str() == placeholder);
+        if !is_likes && !PLACEHOLDERS.contains(&placeholder) {
+            bail!(
+                "unknown placeholder `{placeholder}` in pattern `{pattern}`, expected one of {}",
+                PLACEHOLDERS.join(", ")
+            );
+        }
+        rest = &rest[start + end + 1..];
+    }
+
+    Ok(())
+}
 
 fn is_smiley(token: &str) -> bool {
     token.starts_with(':') && token.len() == 2 || token.contains("<") || token.contains(">")
@@ -27,13 +80,14 @@ fn sanitize(input: &str) -> String {
         input,
         sanitize_filename::Options {
             replacement: " ",
+            windows: true,
             ..Default::default()
         },
     )
 }
 
 fn ignored_tokens(t: &&str) -> bool {
-    !is_smiley(t) && *t != "/" && *t != "/" && !t.starts_with("http")
+    !(is_smiley(t) || *t == "/" || t.starts_with("http"))
 }
 
 fn fix_token(token: &str) -> String {
@@ -64,41 +118,368 @@ fn get_post_title(post: &Post) -> String {
     result.trim().into()
 }
 
+/// Date directories that downloads are sorted into on top of the filename pattern.
+#[derive(Debug, Clone, Copy, ValueEnum)]
+pub enum DateBucket {
+    /// `2023/...`
+    Year,
+    /// `2023/05/...`
+    Month,
+}
+
+/// The base directory with the date bucket of the post appended. Posts without a date go into
+/// `undated`.
+pub fn bucket_directory(
+    base_dir: &Utf8Path,
+    post: &Post,
+    bucket: Option<DateBucket>,
+) -> Utf8PathBuf {
+    let Some(bucket) = bucket else {
+        return base_dir.to_owned();
+    };
+    let Some(date) = post.created_at else {
+        return base_dir.join("undated");
+    };
+    match bucket {
+        DateBucket::Year => base_dir.join(date.format("%Y").to_string()),
+        DateBucket::Month => base_dir.join(date.format("%Y/%m").to_string()),
+    }
+}
+
+/// Trailing path segments of hutt.co image URLs that select a size, like `/images/1234/big`.
+const SIZE_SEGMENTS: &[&str] = &["big", "small", "medium", "thumb", "original"];
+
+/// The file name at the end of a URL, e.g. `IMG_1234.jpg`, or `1234` for `/images/1234/big`.
+/// Relative URLs are taken to be on hutt.co, like the stored links. `None` if the URL doesn't end
+/// in a name.
+pub fn url_basename(url: &str) -> Option<String> {
+    let url = Url::parse("https://hutt.co/").ok()?.join(url).ok()?;
+    let mut segments = url.path_segments()?.filter(|segment| !segment.is_empty());
+    let name = segments.rfind(|segment| !SIZE_SEGMENTS.contains(segment))?;
+    Some(name.to_string())
+}
+
+/// The 1-based position of the link in its post, zero-padded so that the files of a post sort in
+/// order. Falls back to the link id for links that aren't part of `post.links`.
+fn link_index(post: &Post, link_id: i64) -> String {
+    let width = post.links.len().to_string().len();
+    match post.links.iter().position(|link| link.id == link_id) {
+        Some(position) => format!("{:0width$}", position + 1),
+        None => link_id.to_string(),
+    }
+}
+
+/// The extension configured in `extensionMap` for the content type, or `jpeg`/`mp4` by post type.
+fn file_extension(
+    post_type: PostType,
+    content_type: &str,
+    extensions: &HashMap<String, String>,
+) -> String {
+    let content_type = content_type
+        .split(';')
+        .next()
+        .unwrap_or_default()
+        .trim()
+        .to_ascii_lowercase();
+    match extensions.get(&content_type) {
+        Some(extension) => extension.clone(),
+        None => match post_type {
+            PostType::Video => "mp4".to_string(),
+            PostType::Image => "jpeg".to_string(),
+        },
+    }
+}
+
 pub fn get_download_path(
     post: &Post,
     link_id: i64,
+    original_name: Option<&str>,
+    content_type: &str,
+    extensions: &HashMap<String, String>,
     pattern: &str,
     base_dir: impl AsRef<Utf8Path>,
 ) -> Utf8PathBuf {
-    let name = pattern
+    let name = LIKES.replace_all(pattern, |captures: &Captures| {
+        let width = captures
+            .get(1)
+            .and_then(|width| width.as_str().parse().ok())
+            .unwrap_or(0);
+        format!("{:0width$}", post.like_count)
+    });
+    let name = name
         .replace("{post_id}", &post.id.to_string())
         .replace("{title}", &get_post_title(post))
         .replace("{link_id}", &link_id.to_string())
+        .replace("{index}", &link_index(post, link_id))
+        .replace(
+            "{original_name}",
+            &original_name
+                .and_then(|name| Utf8Path::new(name).file_stem())
+                .map(ToOwned::to_owned)
+                .unwrap_or_else(|| link_id.to_string()),
+        )
         .replace(
             "{type}",
             match post.post_type {
                 PostType::Video => "Videos",
                 PostType::Image => "Images",
             },
+        )
+        .replace(
+            "{type_lower}",
+            match post.post_type {
+                PostType::Video => "video",
+                PostType::Image => "image",
+            },
         );
 
-    let parts = name.split('/').map(|part| sanitize(part));
+    let parts = name.split('/').map(sanitize);
     let mut path = base_dir.as_ref().to_owned();
     for part in parts {
         path.push(part.trim());
     }
-    let extension = match post.post_type {
-        PostType::Video => "mp4",
-        PostType::Image => "jpeg",
-    };
-    path.set_extension(extension);
+    path.set_extension(file_extension(post.post_type, content_type, extensions));
 
     path
 }
 
 #[cfg(test)]
 mod tests {
-    use crate::database::{Post, PostType};
+    use std::collections::HashMap;
+
+    use camino::Utf8Path;
+    use chrono::{TimeZone, Utc};
+
+    use super::{bucket_directory, DateBucket};
+    use crate::database::{LinkSource, LinkStatus, Post, PostLink, PostType};
+
+    #[test]
+    fn test_validate_pattern() {
+        assert!(super::validate_pattern("{type}/{post_id} - {title}/{link_id}").is_ok());
+        assert!(super::validate_pattern("{type}/{postid}").is_err());
+        assert!(super::validate_pattern("{type}/{post_id").is_err());
+        assert!(super::validate_pattern("").is_err());
+        assert!(super::validate_pattern("{type_lower}/{likes:05} - {likes}").is_ok());
+        assert!(super::validate_pattern("{likes:5}").is_err());
+    }
+
+    #[test]
+    fn test_likes_and_type_lower() {
+        let post = Post {
+            id: 543321,
+            title: "title".to_string(),
+            tags: vec![],
+            post_type: PostType::Video,
+            links: vec![],
+            creator: "".into(),
+            like_count: 42,
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+
+        let path = super::get_download_path(
+            &post,
+            1234,
+            None,
+            "video/mp4",
+            &HashMap::new(),
+            "{type_lower}/{likes:05} {likes}",
+            ROOT,
+        );
+        assert_eq!(path, "./downloads/video/00042 42.mp4");
+    }
+
+    #[test]
+    fn test_bucket_directory() {
+        let mut post = Post {
+            id: 1,
+            title: "title".to_string(),
+            tags: vec![],
+            post_type: PostType::Image,
+            links: vec![],
+            creator: "".into(),
+            like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+        let base = Utf8Path::new(ROOT);
+
+        assert_eq!(bucket_directory(base, &post, None), "./downloads");
+        assert_eq!(
+            bucket_directory(base, &post, Some(DateBucket::Year)),
+            "./downloads/undated"
+        );
+
+        post.created_at = Some(Utc.with_ymd_and_hms(2023, 5, 12, 10, 0, 0).unwrap());
+        assert_eq!(
+            bucket_directory(base, &post, Some(DateBucket::Year)),
+            "./downloads/2023"
+        );
+        let path = super::get_download_path(
+            &post,
+            7,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_2,
+            bucket_directory(base, &post, Some(DateBucket::Month)),
+        );
+        assert_eq!(path, "./downloads/2023/05/Images/1 - title/7.jpeg");
+    }
+
+    #[test]
+    fn test_original_name() {
+        assert_eq!(
+            super::url_basename("https://cdn.hutt.co/media/IMG_1234.jpg?size=big").as_deref(),
+            Some("IMG_1234.jpg")
+        );
+        assert_eq!(
+            super::url_basename("/images/1234/big").as_deref(),
+            Some("1234")
+        );
+        assert_eq!(
+            super::url_basename("/videos/5003-720.mp4").as_deref(),
+            Some("5003-720.mp4")
+        );
+        assert_eq!(super::url_basename("https://hutt.co/?id=5"), None);
+        assert_eq!(super::url_basename("http://[not a url"), None);
+
+        let post = Post {
+            id: 1,
+            title: "title".to_string(),
+            tags: vec![],
+            post_type: PostType::Image,
+            links: vec![],
+            creator: "".into(),
+            like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+        let pattern = "{post_id}/{original_name}";
+        let path = super::get_download_path(
+            &post,
+            7,
+            Some("IMG_1234.jpg"),
+            "image/jpeg",
+            &HashMap::new(),
+            pattern,
+            ROOT,
+        );
+        assert_eq!(path, "./downloads/1/IMG_1234.jpeg");
+        let path =
+            super::get_download_path(&post, 7, None, "image/jpeg", &HashMap::new(), pattern, ROOT);
+        assert_eq!(path, "./downloads/1/7.jpeg");
+    }
+
+    #[test]
+    fn test_index() {
+        let link = |id| PostLink {
+            id,
+            url: format!("/images/{id}/big"),
+            content_type: "image/jpeg".into(),
+            source: LinkSource::ImageGallery,
+            status: LinkStatus::Pending,
+            error: None,
+            file_path: None,
+            file_path_pattern: None,
+            etag: None,
+            last_modified: None,
+            original_name: None,
+            expected_size: None,
+        };
+        let post = Post {
+            id: 1,
+            title: "title".to_string(),
+            tags: vec![],
+            post_type: PostType::Image,
+            links: (20..32).map(link).collect(),
+            creator: "".into(),
+            like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+        let path = super::get_download_path(
+            &post,
+            22,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            super::FLAT_IMAGE_PATTERN,
+            ROOT,
+        );
+        assert_eq!(path, "./downloads/Images/1 - title - 03.jpeg");
+        let path = super::get_download_path(
+            &post,
+            31,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            super::FLAT_IMAGE_PATTERN,
+            ROOT,
+        );
+        assert_eq!(path, "./downloads/Images/1 - title - 12.jpeg");
+        let path = super::get_download_path(
+            &post,
+            99,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            super::FLAT_IMAGE_PATTERN,
+            ROOT,
+        );
+        assert_eq!(path, "./downloads/Images/1 - title - 99.jpeg");
+    }
+
+    #[test]
+    fn test_extension_map() {
+        let post = Post {
+            id: 1,
+            title: "title".to_string(),
+            tags: vec![],
+            post_type: PostType::Image,
+            links: vec![],
+            creator: "".into(),
+            like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
+            generated_title: None,
+            created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
+        };
+        let extensions = HashMap::from([
+            ("image/jpeg".to_string(), "jpg".to_string()),
+            ("image/webp".to_string(), "webp".to_string()),
+        ]);
+        let path = |content_type| {
+            super::get_download_path(&post, 7, None, content_type, &extensions, "{link_id}", ROOT)
+        };
+
+        assert_eq!(path("image/jpeg"), "./downloads/7.jpg");
+        assert_eq!(path("Image/WebP; charset=binary"), "./downloads/7.webp");
+        assert_eq!(path("image/png"), "./downloads/7.jpeg");
+    }
 
     const PATTERN_1: &str = "{type}/{post_id} - {title} - {link_id}";
     const PATTERN_2: &str = "{type}/{post_id} - {title}/{link_id}";
@@ -114,11 +495,24 @@ mod tests {
             links: vec![],
             creator: "".into(),
             like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 12345, PATTERN_1, ROOT);
+        let title = super::get_download_path(
+            &post,
+            12345,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_1,
+            ROOT,
+        );
         assert_eq!(title.file_name().unwrap(), "543321 - Hello - 12345.jpeg");
     }
 
@@ -132,11 +526,24 @@ mod tests {
             links: vec![],
             creator: "".into(),
             like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 12345, PATTERN_1, ROOT);
+        let title = super::get_download_path(
+            &post,
+            12345,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_1,
+            ROOT,
+        );
         assert_eq!(
             title.file_name().unwrap(),
             "543321 - Snapchat dump photos! So, snapchat is being unfair and won't - 12345.jpeg"
@@ -156,11 +563,24 @@ mod tests {
             links: vec![],
             creator: "".into(),
             like_count: 0,
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 12345, PATTERN_1, ROOT);
+        let title = super::get_download_path(
+            &post,
+            12345,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_1,
+            ROOT,
+        );
         assert_eq!(
             title.file_name().unwrap(),
             "543321 - tailplug boobs ass petplay collar pussy - 12345.jpeg"
@@ -180,11 +600,24 @@ mod tests {
             creator: "".into(),
             like_count: 0,
             title: "presentingggggg..".to_string(),
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 1234, PATTERN_2, ROOT);
+        let title = super::get_download_path(
+            &post,
+            1234,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_2,
+            ROOT,
+        );
         assert_eq!(
             title,
             "./downloads/Images/543321 - presentingggggg/1234.jpeg"
@@ -201,11 +634,24 @@ mod tests {
             creator: "".into(),
             like_count: 0,
             title: "something / something else".to_string(),
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 1234, PATTERN_2, ROOT);
+        let title = super::get_download_path(
+            &post,
+            1234,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_2,
+            ROOT,
+        );
         assert_eq!(
             title,
             "./downloads/Images/543321 - something something else/1234.jpeg"
@@ -222,11 +668,24 @@ mod tests {
             creator: "".into(),
             like_count: 0,
             title: "something/something else".to_string(),
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 1234, PATTERN_2, ROOT);
+        let title = super::get_download_path(
+            &post,
+            1234,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_2,
+            ROOT,
+        );
         assert_eq!(
             title,
             "./downloads/Images/543321 - something something else/1234.jpeg"
@@ -243,11 +702,24 @@ mod tests {
             creator: "".into(),
             like_count: 0,
             title: "My SFW question answers! https://beacons.ai/auroraflower".to_string(),
+            raw_tags: vec![],
+            pinned: false,
             generated_title: None,
             created_at: None,
+            source_page: None,
+            body: None,
+            scraped_at: None,
         };
 
-        let title = super::get_download_path(&post, 1234, PATTERN_2, ROOT);
+        let title = super::get_download_path(
+            &post,
+            1234,
+            None,
+            "image/jpeg",
+            &HashMap::new(),
+            PATTERN_2,
+            ROOT,
+        );
         assert_eq!(
             title,
             "./downloads/Images/543321 - My SFW question answers!/1234.jpeg"
@@ -0,0 +1,518 @@
+//! Scrapes a Hutt creator's posts into a SQLite database and downloads their images and videos.
+//!
+//! The `hutt-archiver` binary is a thin command line wrapper around this crate. To embed the
+//! archiver, load a [`Configuration`], open the database with [`database::connect`] and pass
+//! both to [`DownloadContext::new`]. Each subcommand is a `run` function in [`commands`] that
+//! takes the context and its arguments, and [`scraping::PostScraper`] parses saved post pages
+//! without touching the network.
+
+use std::collections::HashMap;
+
+use camino::{Utf8Path, Utf8PathBuf};
+use chrono::{DateTime, Local, Utc};
+use clap::ValueEnum;
+use color_eyre::eyre::{bail, WrapErr};
+use reqwest::{Client, Proxy};
+use serde::{Deserialize, Deserializer, Serialize};
+use sqlx::SqlitePool;
+
+use crate::database::{Database, PostType, Synchronous};
+
+pub use crate::cookies::{Browser, CookieSource};
+pub use crate::filenames::DateBucket;
+pub use crate::notify::{Notifier, RunCounts, RunSummary};
+
+pub mod commands;
+mod cookies;
+pub mod database;
+mod filenames;
+mod notify;
+pub mod scraping;
+
+pub type Result<T> = color_eyre::Result<T>;
+
+/// Everything a command needs to talk to Hutt and the database.
+pub struct DownloadContext {
+    pub database: Database,
+    pub client: Client,
+    pub configuration: Configuration,
+}
+
+impl DownloadContext {
+    /// Creates the HTTP client, using the configured proxy if there is one.
+    pub fn new(pool: SqlitePool, configuration: Configuration) -> Result<Self> {
+        let mut client = Client::builder();
+        if let Some(proxy) = configuration.proxy()? {
+            client = client.proxy(proxy);
+        }
+
+        Ok(Self {
+            database: Database::new(pool),
+            client: client.build()?,
+            configuration,
+        })
+    }
+}
+
+/// Overrides for the CSS selectors used when scraping posts. Unset fields use the built-in defaults.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct SelectorConfig {
+    pub post_wrapper: Option<String>,
+    pub like_count: Option<String>,
+    pub title: Option<String>,
+    pub tags: Option<String>,
+    pub video_element: Option<String>,
+    pub image_element: Option<String>,
+    /// Marks pinned posts, either on the post wrapper or an element inside it.
+    pub pinned: Option<String>,
+    /// A single comment inside a post, only used with `--with-comments`.
+    pub comment: Option<String>,
+    /// The author and text of a comment, searched inside the `comment` element.
+    pub comment_author: Option<String>,
+    pub comment_text: Option<String>,
+    /// The creator of a post, only used with `metadata --liked` where posts come from several
+    /// creators.
+    pub author: Option<String>,
+}
+
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum DisplayTimezone {
+    #[default]
+    Utc,
+    Local,
+}
+
+impl DisplayTimezone {
+    pub fn format(self, date: DateTime<Utc>) -> String {
+        const FORMAT: &str = "%Y-%m-%d %H:%M %:z";
+        match self {
+            DisplayTimezone::Utc => date.format(FORMAT).to_string(),
+            DisplayTimezone::Local => date.with_timezone(&Local).format(FORMAT).to_string(),
+        }
+    }
+}
+
+/// What downloads images and other direct files. Videos always go through yt-dlp.
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Downloader {
+    #[default]
+    Reqwest,
+    /// The `aria2c` binary, which downloads large files over several connections.
+    Aria2c,
+}
+
+/// The contents of the configuration file, see `config.example.json5`.
+#[derive(Debug, Deserialize, Serialize, Clone, Default)]
+#[serde(rename_all = "camelCase")]
+pub struct Configuration {
+    /// Value of the `Cookie` header. Can be left empty if `cookieSource` is set.
+    #[serde(default)]
+    pub cookie: String,
+
+    /// Reads the cookie from a browser instead, e.g. `{ browser: "firefox" }`. Falls back to
+    /// `cookie` if that fails.
+    pub cookie_source: Option<CookieSource>,
+    pub creator_id: i64,
+    pub creator_name: String,
+    /// Missing post types use the default pattern, keys that aren't a post type are ignored.
+    #[serde(default, deserialize_with = "deserialize_filename_pattern")]
+    pub filename_pattern: Option<HashMap<PostType, String>>,
+
+    pub download_directory: Option<Utf8PathBuf>,
+
+    pub selectors: Option<SelectorConfig>,
+
+    /// HTTP(S) or SOCKS5 proxy used for scraping and downloads, e.g. `socks5://localhost:1080`.
+    pub proxy: Option<String>,
+
+    /// SQLite `synchronous` level, `normal` by default. Use `full` to favor durability over speed.
+    pub database_synchronous: Option<Synchronous>,
+
+    /// Command run after every successful download. `{file}`, `{post_id}` and `{url}` are
+    /// replaced in each of its arguments.
+    pub post_download_hook: Option<String>,
+
+    /// Stops the download run when the post-download hook fails instead of only logging it.
+    pub hook_fail_fast: Option<bool>,
+
+    /// URL that receives a JSON summary via POST when `download` or `metadata` finishes.
+    pub notify_webhook: Option<String>,
+
+    /// Always shows a desktop notification when a run finishes, like `--notify`.
+    pub notify: Option<bool>,
+
+    /// Timezone that timestamps are shown in, `utc` by default.
+    pub timezone: Option<DisplayTimezone>,
+
+    /// Referer sent with video downloads, by creator name. Defaults to the creator's profile URL.
+    pub referers: Option<HashMap<String, String>>,
+
+    /// yt-dlp `--download-archive` file, so yt-dlp skips videos it downloaded before.
+    pub yt_dlp_archive: Option<Utf8PathBuf>,
+
+    /// How many times in a row a rate-limited request is retried before giving up, 10 by default.
+    pub max_rate_limit_retries: Option<u32>,
+
+    /// Makes `metadata` stop at the first page without new posts, like `--only-new`.
+    pub only_new: Option<bool>,
+
+    /// File extension by content type, e.g. `{ "image/jpeg": "jpg" }`. Content types that aren't
+    /// listed get `jpeg` or `mp4`.
+    pub extension_map: Option<HashMap<String, String>>,
+
+    /// Downloads images with `aria2c` instead of the built-in client, `reqwest` by default. aria2c
+    /// can't use a SOCKS `proxy`.
+    pub downloader: Option<Downloader>,
+}
+
+impl Configuration {
+    /// The example configuration that `init` starts from.
+    pub const TEMPLATE: &'static str = include_str!("../config.example.json5");
+
+    /// Supported configuration file extensions, in order of priority.
+    const EXTENSIONS: &'static [&'static str] = &[
+        "json5",
+        #[cfg(feature = "toml")]
+        "toml",
+        #[cfg(feature = "yaml")]
+        "yaml",
+        #[cfg(feature = "yaml")]
+        "yml",
+    ];
+
+    fn parse(path: &Utf8Path, content: &str) -> Result<Self> {
+        let config = match path.extension() {
+            #[cfg(feature = "toml")]
+            Some("toml") => toml::from_str(content)?,
+            #[cfg(feature = "yaml")]
+            Some("yaml" | "yml") => serde_yaml::from_str(content)?,
+            _ => json5::from_str(content)?,
+        };
+        Ok(config)
+    }
+
+    /// Finds the configuration file at `path`, or a file next to it with one of the other
+    /// supported extensions.
+    pub fn find(path: &Utf8Path) -> Option<Utf8PathBuf> {
+        let existing: Vec<_> = Self::EXTENSIONS
+            .iter()
+            .map(|extension| path.with_extension(extension))
+            .filter(|candidate| candidate.is_file())
+            .collect();
+        if existing.len() > 1 {
+            eprintln!(
+                "Found multiple configuration files, using `{}` and ignoring the others.",
+                existing[0]
+            );
+        }
+        existing.into_iter().next()
+    }
+
+    /// Loads the configuration from `path`, or from a file next to it with one of the other
+    /// supported extensions.
+    pub fn load(path: &Utf8Path) -> Result<Self> {
+        match Self::find(path) {
+            Some(path) => Self::load_file(&path),
+            None => bail!("no configuration found at `{path}`, run `hutt-archiver init` first"),
+        }
+    }
+
+    /// Loads the configuration from exactly `path`, without creating a default one.
+    pub fn load_file(path: &Utf8Path) -> Result<Self> {
+        if !path.is_file() {
+            bail!("configuration file `{path}` does not exist");
+        }
+        let content = std::fs::read_to_string(path)?;
+        let mut config = Self::parse(path, &content)?;
+        config.expand_env()?;
+
+        Ok(config)
+    }
+
+    /// Expands `~`, `$VAR` and `${VAR}` in the download directory and the filename patterns.
+    fn expand_env(&mut self) -> Result<()> {
+        if let Some(directory) = &self.download_directory {
+            let expanded = expand_env(directory.as_str())
+                .wrap_err_with(|| format!("invalid download directory `{directory}`"))?;
+            self.download_directory = Some(expanded.into());
+        }
+        if let Some(patterns) = &mut self.filename_pattern {
+            for (post_type, pattern) in patterns.iter_mut() {
+                *pattern = expand_env(pattern)
+                    .wrap_err_with(|| format!("invalid filename pattern for {post_type:?}"))?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Checks the parts of the configuration that can't be expressed in its types.
+    pub fn validate(&self) -> Result<()> {
+        for (post_type, pattern) in self.filename_pattern() {
+            filenames::validate_pattern(&pattern)
+                .wrap_err_with(|| format!("invalid filename pattern for {post_type:?}"))?;
+        }
+        self.proxy()?;
+        if let (Some(Downloader::Aria2c), Some(proxy)) = (self.downloader, &self.proxy) {
+            if !proxy.starts_with("http") {
+                bail!("aria2c only supports http and https proxies, not `{proxy}`. Set `downloader` to `reqwest` to use it.");
+            }
+        }
+        for (content_type, extension) in self.extension_map() {
+            if extension.is_empty() || extension.contains(['/', '\\']) {
+                bail!("invalid extension `{extension}` for `{content_type}` in extensionMap");
+            }
+        }
+        Ok(())
+    }
+
+    /// `extensionMap` with lowercase content types and the extensions without a leading dot.
+    pub fn extension_map(&self) -> HashMap<String, String> {
+        self.extension_map
+            .iter()
+            .flatten()
+            .map(|(content_type, extension)| {
+                (
+                    content_type.trim().to_ascii_lowercase(),
+                    extension.trim().trim_start_matches('.').to_string(),
+                )
+            })
+            .collect()
+    }
+
+    pub fn proxy(&self) -> Result<Option<Proxy>> {
+        let Some(url) = &self.proxy else {
+            return Ok(None);
+        };
+        let scheme = url.split_once("://").map(|(scheme, _)| scheme);
+        if !matches!(scheme, Some("http" | "https" | "socks5" | "socks5h")) {
+            bail!("unsupported proxy `{url}`, expected an http, https, socks5 or socks5h URL");
+        }
+        let proxy = Proxy::all(url).wrap_err_with(|| format!("invalid proxy `{url}`"))?;
+        Ok(Some(proxy))
+    }
+
+    /// The configuration with all defaults applied and the cookie redacted.
+    pub fn effective(&self) -> Self {
+        Self {
+            cookie: "<redacted>".to_string(),
+            download_directory: Some(self.download_directory().to_owned()),
+            filename_pattern: Some(self.filename_pattern()),
+            database_synchronous: Some(self.database_synchronous.unwrap_or_default()),
+            timezone: Some(self.timezone.unwrap_or_default()),
+            downloader: Some(self.downloader.unwrap_or_default()),
+            max_rate_limit_retries: Some(self.max_rate_limit_retries()),
+            ..self.clone()
+        }
+    }
+
+    pub fn download_directory(&self) -> &Utf8Path {
+        self.download_directory
+            .as_deref()
+            .unwrap_or_else(|| Utf8Path::new("downloads"))
+    }
+
+    /// Referer for the videos of `creator`, which the CDN checks against the post's location.
+    pub fn referer(&self, creator: &str) -> String {
+        self.referers
+            .as_ref()
+            .and_then(|referers| referers.get(creator))
+            .cloned()
+            .unwrap_or_else(|| format!("https://hutt.co/{creator}"))
+    }
+
+    pub fn max_rate_limit_retries(&self) -> u32 {
+        self.max_rate_limit_retries.unwrap_or(10)
+    }
+
+    /// The filename pattern of every post type, with the defaults filled in for missing ones.
+    pub fn filename_pattern(&self) -> HashMap<PostType, String> {
+        let mut patterns: HashMap<_, _> = [
+            (
+                PostType::Image,
+                "{type}/{post_id} - {title}/{link_id}".to_string(),
+            ),
+            (PostType::Video, "{type}/{post_id} - {title}".to_string()),
+        ]
+        .into_iter()
+        .collect();
+        if let Some(configured) = &self.filename_pattern {
+            patterns.extend(configured.clone());
+        }
+        patterns
+    }
+
+    #[cfg(test)]
+    pub fn test() -> Self {
+        Self {
+            download_directory: Some(Utf8PathBuf::from("downloads")),
+            cookie: "cookie".to_string(),
+            cookie_source: None,
+            creator_id: 1,
+            creator_name: "creator".to_string(),
+            filename_pattern: Some(
+                [
+                    (PostType::Image, "{link_id}".to_string()),
+                    (PostType::Video, "{link_id}".to_string()),
+                ]
+                .iter()
+                .cloned()
+                .collect(),
+            ),
+            selectors: None,
+            database_synchronous: None,
+            proxy: None,
+            post_download_hook: None,
+            hook_fail_fast: None,
+            notify_webhook: None,
+            notify: None,
+            timezone: None,
+            referers: None,
+            yt_dlp_archive: None,
+            max_rate_limit_retries: None,
+            only_new: None,
+            extension_map: None,
+            downloader: None,
+        }
+    }
+}
+
+/// Reads `filenamePattern` with a warning for keys that aren't a post type, most likely typos,
+/// instead of failing to load the whole configuration.
+fn deserialize_filename_pattern<'de, D>(
+    deserializer: D,
+) -> std::result::Result<Option<HashMap<PostType, String>>, D::Error>
+where
+    D: Deserializer<'de>,
+{
+    let Some(patterns) = Option::<HashMap<String, String>>::deserialize(deserializer)? else {
+        return Ok(None);
+    };
+    let mut known = HashMap::new();
+    for (key, pattern) in patterns {
+        match PostType::from_str(&key, true) {
+            Ok(post_type) => {
+                known.insert(post_type, pattern);
+            }
+            Err(_) => eprintln!(
+                "Ignoring filename pattern for unknown post type `{key}`, expected `image` or `video`."
+            ),
+        }
+    }
+    Ok(Some(known))
+}
+
+/// Replaces a leading `~` with the home directory and `$VAR` or `${VAR}` with the value of the
+/// environment variable. Unset variables are an error.
+fn expand_env(value: &str) -> Result<String> {
+    let mut result = String::with_capacity(value.len());
+    let mut rest = value;
+
+    if rest == "~" || rest.starts_with("~/") {
+        let home = std::env::var("HOME")
+            .or_else(|_| std::env::var("USERPROFILE"))
+            .wrap_err("could not determine the home directory to expand `~`")?;
+        result.push_str(&home);
+        rest = &rest[1..];
+    }
+
+    while let Some(start) = rest.find('$') {
+        result.push_str(&rest[..start]);
+        rest = &rest[start + 1..];
+        let (name, remaining) = if let Some(braced) = rest.strip_prefix('{') {
+            let Some(end) = braced.find('}') else {
+                bail!("unclosed `${{` in `{value}`");
+            };
+            (&braced[..end], &braced[end + 1..])
+        } else {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            (&rest[..end], &rest[end..])
+        };
+        if name.is_empty() {
+            bail!("missing variable name after `$` in `{value}`");
+        }
+        let variable = std::env::var(name)
+            .wrap_err_with(|| format!("environment variable `{name}` is not set"))?;
+        result.push_str(&variable);
+        rest = remaining;
+    }
+    result.push_str(rest);
+
+    Ok(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_env, Configuration, Downloader};
+    use crate::database::PostType;
+
+    #[test]
+    fn test_expand_env() {
+        std::env::set_var("HUTT_TEST_DIR", "/data");
+        assert_eq!(expand_env("$HUTT_TEST_DIR/hutt").unwrap(), "/data/hutt");
+        assert_eq!(expand_env("${HUTT_TEST_DIR}s").unwrap(), "/datas");
+        assert_eq!(
+            expand_env("{type}/{post_id} - {title}").unwrap(),
+            "{type}/{post_id} - {title}"
+        );
+        assert!(expand_env("$HUTT_TEST_UNSET_VARIABLE/hutt").is_err());
+        assert!(expand_env("${HUTT_TEST_DIR").is_err());
+    }
+
+    #[test]
+    fn test_filename_pattern_defaults() {
+        let config: Configuration = json5::from_str(
+            r#"{
+                creatorId: 1,
+                creatorName: "creator",
+                filenamePattern: { image: "{link_id}", imgae: "{title}" },
+            }"#,
+        )
+        .unwrap();
+
+        let patterns = config.filename_pattern();
+        assert_eq!(patterns.len(), 2);
+        assert_eq!(patterns[&PostType::Image], "{link_id}");
+        assert_eq!(patterns[&PostType::Video], "{type}/{post_id} - {title}");
+        assert!(config.validate().is_ok());
+    }
+
+    #[test]
+    fn test_extension_map() {
+        let mut config: Configuration = json5::from_str(
+            r#"{
+                creatorId: 1,
+                creatorName: "creator",
+                extensionMap: { "Image/JPEG": ".jpg" },
+            }"#,
+        )
+        .unwrap();
+
+        let extensions = config.extension_map();
+        assert_eq!(extensions.len(), 1);
+        assert_eq!(extensions["image/jpeg"], "jpg");
+        assert!(config.validate().is_ok());
+
+        config.extension_map = Some([("image/png".to_string(), "a/b".to_string())].into());
+        assert!(config.validate().is_err());
+    }
+
+    #[test]
+    fn test_aria2c_proxy() {
+        let mut config = Configuration {
+            downloader: Some(Downloader::Aria2c),
+            proxy: Some("http://localhost:8080".to_string()),
+            ..Configuration::test()
+        };
+        assert!(config.validate().is_ok());
+
+        config.proxy = Some("socks5://localhost:1080".to_string());
+        assert!(config.validate().is_err());
+        config.downloader = Some(Downloader::Reqwest);
+        assert!(config.validate().is_ok());
+    }
+}
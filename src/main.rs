@@ -1,268 +1,950 @@
-use std::collections::HashMap;
+use std::collections::BTreeSet;
+use std::time::{Duration, Instant};
 
 use camino::{Utf8Path, Utf8PathBuf};
 use clap::{Parser, Subcommand};
-use reqwest::Client;
-use serde::Deserialize;
-use sqlx::SqlitePool;
-use tracing::info;
+use color_eyre::eyre::bail;
+use hutt_archiver::commands::disk_usage::DiskUsageArgs;
+use hutt_archiver::commands::download::{
+    parse_duration, parse_regex, parse_size, DownloadArgs, PostOrder, TitleFilter,
+    PARTIAL_FAILURE_EXIT_CODE,
+};
+use hutt_archiver::commands::import::ImportArgs;
+use hutt_archiver::commands::init::InitArgs;
+use hutt_archiver::commands::manifest::ManifestArgs;
+use hutt_archiver::commands::metadata::MetadataArgs;
+use hutt_archiver::commands::rename::RenameArgs;
+use hutt_archiver::commands::reparse::ReparseArgs;
+use hutt_archiver::commands::resolve_creator::ResolveCreatorArgs;
+use hutt_archiver::commands::set_dates::{DateCurve, DateStrategy, SetDatesArgs};
+use hutt_archiver::database::{LinkStatus, Page, PostType};
+use hutt_archiver::{
+    commands, database, Configuration, DateBucket, DownloadContext, Notifier, Result, RunCounts,
+    RunSummary,
+};
+use tracing::{info, warn};
+use tracing_appender::non_blocking::WorkerGuard;
+use tracing_appender::rolling::{RollingFileAppender, Rotation};
+use tracing_subscriber::layer::SubscriberExt;
+use tracing_subscriber::util::SubscriberInitExt;
 use tracing_subscriber::EnvFilter;
 
-use crate::commands::download::DownloadArgs;
-use crate::commands::metadata::MetadataArgs;
-use crate::commands::set_dates::SetDatesArgs;
-use crate::database::{Database, LinkStatus, PostType};
+#[derive(Parser, Debug)]
+pub struct Args {
+    #[clap(short, long)]
+    pub log: bool,
 
-mod commands;
-mod database;
-mod filenames;
+    /// Also writes the logs to this file, rotated daily with the date appended to the name.
+    /// Works with or without `--log`.
+    #[clap(long, global = true)]
+    pub log_file: Option<Utf8PathBuf>,
 
-pub type Result<T> = color_eyre::Result<T>;
+    /// Skips the confirmation prompt of destructive commands.
+    #[clap(short, long, global = true)]
+    pub yes: bool,
 
-pub struct DownloadContext {
-    pub database: Database,
-    pub client: Client,
-    pub configuration: Configuration,
-}
+    /// Directory that holds the configuration, database, backups and (by default) downloads.
+    /// Defaults to the current directory.
+    #[clap(long, global = true)]
+    pub data_dir: Option<Utf8PathBuf>,
 
-impl DownloadContext {
-    pub fn new(pool: SqlitePool, configuration: Configuration) -> Self {
-        Self {
-            database: Database::new(pool),
-            client: Client::new(),
-            configuration,
-        }
-    }
-}
+    /// Reads the configuration from this file instead of `config.json5` in the data directory.
+    /// Unlike the default location, it's an error if the file doesn't exist.
+    #[clap(long, global = true)]
+    pub config: Option<Utf8PathBuf>,
 
-#[derive(Parser, Debug)]
-pub struct Args {
-    #[clap(short, long)]
-    pub log: bool,
+    /// Shows a desktop notification when `download` or `metadata` finishes.
+    #[clap(long, global = true)]
+    pub notify: bool,
 
     #[command(subcommand)]
     pub command: Command,
 }
 
+impl Args {
+    /// Resolves a path relative to the data directory, if one is set.
+    pub fn data_path(&self, path: impl AsRef<Utf8Path>) -> Utf8PathBuf {
+        match &self.data_dir {
+            Some(data_dir) => data_dir.join(path),
+            None => path.as_ref().to_owned(),
+        }
+    }
+}
+
 #[derive(Debug, Subcommand)]
 pub enum Command {
     /// Gathers all the metadata for the creator in the database.
-    Metadata,
+    Metadata {
+        /// How many consecutive empty pages to skip before assuming there are no more posts.
+        #[clap(long, default_value_t = 0)]
+        empty_page_tolerance: u32,
+
+        /// Saves the raw HTML of every post to `posts/<id>.html` in the download directory.
+        #[clap(long, alias = "include-html")]
+        save_html: bool,
+
+        /// How many posts to insert per database transaction.
+        #[clap(long, default_value_t = 100)]
+        batch_size: usize,
+
+        /// Doesn't store posts of this type, e.g. `--skip videos`.
+        #[clap(long, value_enum)]
+        skip: Option<PostType>,
+
+        /// Stops at the first page whose posts are all in the database already. Assumes that
+        /// new posts only show up before the known ones.
+        #[clap(long, conflicts_with = "full")]
+        only_new: bool,
+
+        /// Scans all pages from the first one, even if `onlyNew` is set in the configuration or an
+        /// interrupted run left a checkpoint.
+        #[clap(long)]
+        full: bool,
+
+        /// Starts scraping at this page instead of the first one or the checkpoint. After every
+        /// page the next one is saved to `.hutt-scrape-state`, and a run that stopped before the
+        /// last page continues from there.
+        #[clap(long)]
+        since_page: Option<u32>,
+
+        /// Stops after this many pages no matter what. Hitting the limit usually means that
+        /// something is wrong, e.g. an expired cookie.
+        #[clap(long)]
+        max_pages: Option<u32>,
+
+        /// Stops fetching pages after this long, e.g. `30m` or `1h30m`. Posts from the pages
+        /// fetched until then are kept.
+        #[clap(long, value_parser = parse_duration)]
+        deadline: Option<Duration>,
+
+        /// Also stores the comments shown with each post. Comments that Hutt only loads on demand
+        /// are missed.
+        #[clap(long)]
+        with_comments: bool,
+
+        /// Updates the title, text, tags and likes of posts that are already stored and adds any
+        /// new links to them. Links that were stored before keep their download status. Scans all
+        /// pages like `--full`.
+        #[clap(long, alias = "update", conflicts_with = "only_new")]
+        refetch_metadata: bool,
+
+        /// Scrapes the posts liked by the account the cookie belongs to instead of the configured
+        /// creator's. Each post is stored with its own creator, taken from the `author` selector.
+        /// No checkpoint is saved.
+        #[clap(long)]
+        liked: bool,
+    },
+
+    /// Fetches the pages from `start` to `end` again and stores the posts that are missing from the
+    /// database, e.g. because an older version couldn't extract their links.
+    Rescan {
+        start: u32,
+        end: u32,
+
+        /// Saves the raw HTML of every post to `posts/<id>.html` in the download directory.
+        #[clap(long)]
+        save_html: bool,
+    },
 
     /// Downloads all the not-yet downloaded media for the creator that's stored in the database.
     Download {
         #[clap(short, long)]
         dry_run: bool,
+
+        /// Overrides the configured download directory for this run.
+        #[clap(long)]
+        output_dir: Option<Utf8PathBuf>,
+
+        /// Keeps downloading after a link fails instead of stopping. The process exits with code 2
+        /// if any link failed.
+        #[clap(long)]
+        keep_going: bool,
+
+        /// Skips files larger than this size, e.g. `500M` or `2G`.
+        #[clap(long, value_parser = parse_size)]
+        max_size: Option<u64>,
+
+        /// Doesn't start new downloads once this much has been downloaded, e.g. `10G`. Downloads
+        /// that are already running are finished.
+        #[clap(long, value_parser = parse_size)]
+        quota: Option<u64>,
+
+        /// Doesn't start new downloads after this long, e.g. `30m` or `1h30m`. Downloads that
+        /// are running are finished, the rest stays pending for the next run.
+        #[clap(long, value_parser = parse_duration)]
+        deadline: Option<Duration>,
+
+        /// Re-checks existing images with a conditional request and downloads them again if they
+        /// changed on the server.
+        #[clap(long)]
+        check_updates: bool,
+
+        /// Prints one JSON object per processed link to stdout instead of showing a progress bar.
+        #[clap(long)]
+        json: bool,
+
+        /// How many images to download at the same time.
+        #[clap(long, default_value_t = 1)]
+        image_concurrency: usize,
+
+        /// How many videos to download with yt-dlp at the same time.
+        #[clap(long, default_value_t = 1)]
+        video_concurrency: usize,
+
+        /// Processes posts from oldest or newest first, by date. Posts without a date are ordered
+        /// by id and come before the dated ones with `oldest` and after them with `newest`.
+        #[clap(long, value_enum)]
+        order: Option<PostOrder>,
+
+        /// Only downloads posts whose title matches this regex.
+        #[clap(long, value_parser = parse_regex)]
+        title_match: Option<String>,
+
+        /// Skips posts whose title matches this regex.
+        #[clap(long, value_parser = parse_regex)]
+        title_exclude: Option<String>,
+
+        /// Matches `--title-match` and `--title-exclude` case-sensitively.
+        #[clap(long)]
+        case_sensitive: bool,
+
+        /// Only retries links that failed in an earlier run, pending links are left alone.
+        #[clap(long, alias = "retry-errors-only")]
+        retry_errors: bool,
+
+        /// Detects the type of downloaded images from their first bytes and fixes the extension
+        /// and stored content type if the scraped one was wrong.
+        #[clap(long)]
+        sniff_content_type: bool,
+
+        /// Sorts the files into `<year>` or `<year>/<month>` directories by post date, on top of
+        /// the filename pattern. Posts without a date go into `undated`.
+        #[clap(long, value_enum)]
+        bucket_by: Option<DateBucket>,
+
+        /// Asks the server for the size of every file before downloading and shows progress in
+        /// bytes, which gives a better ETA when sizes vary a lot. Sizes are remembered, so only
+        /// the first run pays for the extra requests.
+        #[clap(long)]
+        accurate_progress: bool,
+
+        /// Lists the posts that would be downloaded and only downloads the ones picked from the
+        /// list. The others stay pending. Downloads everything when not run in a terminal.
+        #[clap(long, alias = "interactive")]
+        select: bool,
+
+        /// Saves all images into a single directory as `<post id> - <title> - <index>` instead of
+        /// a directory per post, overriding the configured image pattern for this run.
+        #[clap(long)]
+        flatten: bool,
     },
 
     /// Reset the status of all downloads to `Pending`.
-    ResetDownloads,
+    ResetDownloads {
+        /// Only reset links of posts by this creator.
+        #[clap(long)]
+        creator: Option<String>,
+
+        /// Only reset links of posts of this type.
+        #[clap(long = "type", value_enum)]
+        post_type: Option<PostType>,
+    },
 
     /// Creates a backup of the database.
     BackupDatabase,
 
     /// Prints a report of the current state of the database.
-    Report,
+    Report {
+        /// Only lists the posts that have links which failed to download, with their errors.
+        #[clap(long)]
+        errors_only: bool,
+
+        /// Lists at most this many posts.
+        #[clap(long, requires = "errors_only")]
+        limit: Option<u32>,
+
+        /// Skips this many posts before listing.
+        #[clap(long, default_value_t, requires = "errors_only")]
+        offset: u32,
+    },
 
     /// Renames all the files in the database to match the new filename pattern.
     Rename {
         #[clap(short, long)]
         dry_run: bool,
+
+        /// Writes the planned moves as JSON to this file. Only valid with `--dry-run`.
+        #[clap(long, requires = "dry_run")]
+        plan: Option<Utf8PathBuf>,
+
+        /// Overrides the configured download directory for this run.
+        #[clap(long)]
+        output_dir: Option<Utf8PathBuf>,
+
+        /// Copies the files to their new paths and keeps the originals, so a new pattern can be
+        /// checked before the old files are deleted.
+        #[clap(long)]
+        copy: bool,
+
+        /// Sorts the files into `<year>` or `<year>/<month>` directories by post date, on top of
+        /// the filename pattern. Posts without a date go into `undated`.
+        #[clap(long, value_enum)]
+        bucket_by: Option<DateBucket>,
+
+        /// Refuses to rename if any directory would be merged with another or left empty, instead
+        /// of only warning about it.
+        #[clap(long)]
+        preserve_structure: bool,
     },
 
     /// Sets the dates for all posts in the database to a range between `start` and `end`. It will interpolate the dates between the two.
+    /// Posts that already have a date are skipped unless `--force` is passed.
     /// This means, the first post will have the date of `start` and the last post will have the date of `end`, with all the posts in between having dates in between.
-    SetDates { start: String, end: String },
-}
+    SetDates {
+        start: String,
+        end: String,
+
+        /// Prints the date each post would get without changing the database.
+        #[clap(short, long)]
+        dry_run: bool,
+
+        /// How to spread the posts between the two dates.
+        #[clap(long = "by", value_enum, default_value_t)]
+        strategy: DateStrategy,
+
+        /// Clusters the posts towards the start (`ease-in`) or end (`ease-out`) of the range.
+        #[clap(long, value_enum, default_value_t)]
+        curve: DateCurve,
+
+        /// Also overwrites the dates of posts that already have one.
+        #[clap(long)]
+        force: bool,
+    },
 
-#[derive(Debug, Deserialize, Clone)]
-#[serde(rename_all = "camelCase")]
-pub struct Configuration {
-    pub cookie: String,
-    pub creator_id: i64,
-    pub creator_name: String,
-    pub filename_pattern: Option<HashMap<PostType, String>>,
+    /// Merges the posts and links of another database into this one.
+    Import { other: Utf8PathBuf },
 
-    pub download_directory: Option<Utf8PathBuf>,
+    /// Extracts posts and links again from HTML saved with `metadata --save-html`, adding any
+    /// links that are missing from the database without fetching anything.
+    Reparse { dir: Utf8PathBuf },
+
+    /// Validates the configuration and optionally prints the effective settings.
+    Config {
+        /// Prints the configuration with all defaults applied as JSON.
+        #[clap(long)]
+        show: bool,
+    },
+
+    /// Lists all creators in the database with their post and link counts.
+    ListCreators {
+        /// Lists at most this many creators.
+        #[clap(long)]
+        limit: Option<u32>,
+
+        /// Skips this many creators before listing.
+        #[clap(long, default_value_t)]
+        offset: u32,
+    },
+
+    /// Shows how much space the downloaded files take up by post type and creator.
+    DiskUsage {
+        /// How many of the largest posts to list.
+        #[clap(long, default_value_t = 10)]
+        top: usize,
+    },
+
+    /// Looks up the `creatorId` and `creatorName` for a creator's profile URL, e.g.
+    /// `https://hutt.co/someone`.
+    ResolveCreator { url: String },
+
+    /// Checks that yt-dlp, the database, the download directory and the cookie are all usable.
+    Doctor,
+
+    /// Downloads the creator's avatar, banner and bio to `profile/` in the download directory.
+    DownloadProfile,
+
+    /// Writes a `sha256sum`-compatible manifest of all downloaded files, with paths relative to
+    /// the download directory.
+    Manifest {
+        /// The manifest file to write, or to check with `--verify`.
+        output: Utf8PathBuf,
+
+        /// Checks the files against an existing manifest instead and reports any mismatches.
+        #[clap(long)]
+        verify: bool,
+    },
+
+    /// Creates the configuration file and the database. Run this once before anything else.
+    Init {
+        /// Asks for the cookie and the creator's profile URL and fills them in, instead of
+        /// printing instructions on how to do it by hand.
+        #[clap(long)]
+        interactive: bool,
+    },
 }
 
-impl Configuration {
-    pub fn load() -> Result<Self> {
-        const DEFAULT_CONFIG: &'static str = include_str!("../config.example.json5");
-
-        let path = Utf8Path::new("config.json5");
-        let config = if path.is_file() {
-            let content = std::fs::read_to_string(path)?;
-            json5::from_str(&content)?
-        } else {
-            println!("Created default configuration file at `config.json5`.");
-            println!("Short instructions:");
-            println!("");
-
-            println!("1. Log in to Hutt in your browser.");
-            println!("2. Open the developer tools (F12) and go to the Network tab.");
-            println!("3. Refresh the page.");
-            println!(
-                "4. Find the request to any of the API endpoints and copy the `Cookie` header."
-            );
-            println!(
-                "5. Paste the `Cookie` header into the `cookie` field in the configuration file."
-            );
-            println!("6. Find the numerical ID of the creator by looking at the `/is-live?id=...` request. The number at the end is their ID.");
-            println!("7. Set the `creatorId` and `creatorName` fields to the creator you want to download.");
+impl Command {
+    pub fn output_dir(&self) -> Option<&Utf8Path> {
+        match self {
+            Command::Download { output_dir, .. } | Command::Rename { output_dir, .. } => {
+                output_dir.as_deref()
+            }
+            _ => None,
+        }
+    }
 
-            std::fs::write(path, DEFAULT_CONFIG)?;
-            std::process::exit(1);
-        };
+    /// Whether the command can't run without a configuration file. The others only need the
+    /// database and fall back to the default configuration.
+    pub fn needs_configuration(&self) -> bool {
+        !matches!(
+            self,
+            Command::ResetDownloads { .. }
+                | Command::BackupDatabase
+                | Command::Report { .. }
+                | Command::SetDates { .. }
+                | Command::Import { .. }
+                | Command::ListCreators { .. }
+                | Command::DiskUsage { .. }
+        )
+    }
+}
+
+/// Asks the user to confirm a destructive action. Non-interactive runs have to pass `--yes`.
+fn confirm(action: &str, yes: bool) -> Result<()> {
+    use std::io::{IsTerminal, Write};
 
-        Ok(config)
+    if yes {
+        return Ok(());
+    }
+    if !std::io::stdin().is_terminal() {
+        bail!("refusing to {action} without confirmation, pass --yes to proceed");
     }
 
-    pub fn download_directory(&self) -> &Utf8Path {
-        self.download_directory
-            .as_deref()
-            .unwrap_or_else(|| Utf8Path::new("downloads"))
+    print!("This will {action}. Are you sure? [y/N] ");
+    std::io::stdout().flush()?;
+    let mut answer = String::new();
+    std::io::stdin().read_line(&mut answer)?;
+    match answer.trim().to_lowercase().as_str() {
+        "y" | "yes" => Ok(()),
+        _ => bail!("aborted"),
     }
+}
 
-    pub fn filename_pattern(&self) -> HashMap<PostType, String> {
-        self.filename_pattern.clone().unwrap_or_else(|| {
-            [
-                (
-                    PostType::Image,
-                    "{type}/{post_id} - {title}/{link_id}".to_string(),
-                ),
-                (PostType::Video, "{type}/{post_id} - {title}".to_string()),
-            ]
+async fn print_errors(context: DownloadContext, page: Page) -> Result<()> {
+    let mut posts = context.database.stream_errors(page);
+    let mut post_count = 0;
+    let mut link_count = 0;
+
+    while let Some(post) = posts.next_post().await? {
+        let failed: Vec<_> = post
+            .links
             .iter()
-            .cloned()
-            .collect()
-        })
-    }
+            .filter(|l| l.status == LinkStatus::Error)
+            .collect();
+        if failed.is_empty() {
+            continue;
+        }
 
-    #[cfg(test)]
-    pub fn test() -> Self {
-        Self {
-            download_directory: Some(Utf8PathBuf::from("downloads")),
-            cookie: "cookie".to_string(),
-            creator_id: 1,
-            creator_name: "creator".to_string(),
-            filename_pattern: Some(
-                [
-                    (PostType::Image, "{link_id}".to_string()),
-                    (PostType::Video, "{link_id}".to_string()),
-                ]
-                .iter()
-                .cloned()
-                .collect(),
-            ),
+        let title: String = post.title.split_whitespace().collect::<Vec<_>>().join(" ");
+        let title: String = title.chars().take(60).collect();
+        println!("{} {}", post.id, title);
+        for link in &failed {
+            println!(
+                "  {} {}",
+                link.url,
+                link.error.as_deref().unwrap_or("(no error message)")
+            );
         }
+        post_count += 1;
+        link_count += failed.len();
     }
+
+    println!("{link_count} failed links in {post_count} posts");
+
+    Ok(())
 }
 
 async fn print_report(context: DownloadContext) -> Result<()> {
-    let posts = context.database.fetch_all().await?;
-    let total_count: usize = posts.iter().map(|p| p.links.len()).sum();
-    let downloaded_count: usize = posts
-        .iter()
-        .map(|p| {
-            p.links
-                .iter()
-                .filter(|l| l.status == LinkStatus::Downloaded)
-                .count()
-        })
-        .sum();
-
-    let error_count: usize = posts
-        .iter()
-        .map(|p| {
-            p.links
-                .iter()
-                .filter(|l| l.status == LinkStatus::Error)
-                .count()
-        })
-        .sum();
-
-    let pending_count: usize = posts
-        .iter()
-        .map(|p| {
-            p.links
-                .iter()
-                .filter(|l| l.status == LinkStatus::Pending)
-                .count()
-        })
-        .sum();
+    let mut posts = context.database.stream_all();
+    let mut total_count = 0;
+    let mut downloaded_count = 0;
+    let mut error_count = 0;
+    let mut pending_count = 0;
+    let mut skipped_count = 0;
+    let mut pages = BTreeSet::new();
+    let mut scraped_at = BTreeSet::new();
+
+    while let Some(post) = posts.next_post().await? {
+        total_count += post.links.len();
+        for link in &post.links {
+            match link.status {
+                LinkStatus::Downloaded => downloaded_count += 1,
+                LinkStatus::Error => error_count += 1,
+                LinkStatus::Pending => pending_count += 1,
+                LinkStatus::SkippedTooLarge => skipped_count += 1,
+            }
+        }
+        pages.extend(post.source_page);
+        scraped_at.extend(post.scraped_at);
+    }
 
     println!("Total links: {}", total_count);
     println!("Downloaded links: {}", downloaded_count);
     println!("Error links: {}", error_count);
     println!("Pending links: {}", pending_count);
+    println!("Skipped (too large) links: {}", skipped_count);
+
+    let without_links = context.database.count_posts_without_links().await?;
+    if without_links > 0 {
+        println!("{} posts had no downloadable links", without_links);
+    }
+
+    if let (Some(first), Some(last)) = (pages.first(), pages.last()) {
+        println!("Scraped pages: {}-{}", first, last);
+        let missing: Vec<String> = (*first..=*last)
+            .filter(|page| !pages.contains(page))
+            .map(|page| page.to_string())
+            .collect();
+        if !missing.is_empty() {
+            println!("Pages without posts: {}", missing.join(", "));
+        }
+    }
+
+    let timezone = context.configuration.timezone.unwrap_or_default();
+    if let (Some(oldest), Some(newest)) = (scraped_at.first(), scraped_at.last()) {
+        println!(
+            "Scraped between {} and {}",
+            timezone.format(*oldest),
+            timezone.format(*newest)
+        );
+    }
+
+    let runs = context.database.recent_runs(10).await?;
+    if !runs.is_empty() {
+        println!();
+        println!("Recent runs:");
+        for run in &runs {
+            println!(
+                "  {} {}: {}",
+                timezone.format(run.started_at),
+                run.command,
+                run.describe()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+async fn print_creators(context: DownloadContext, page: Page) -> Result<()> {
+    let creators = context.database.creator_summaries(page).await?;
+    if creators.is_empty() {
+        println!("No posts in the database.");
+        return Ok(());
+    }
+
+    for creator in creators {
+        let dates = match (creator.first_date, creator.last_date) {
+            (Some(first), Some(last)) => format!(", {first} to {last}"),
+            _ => String::new(),
+        };
+        println!(
+            "{}: {} posts, {} downloaded, {} pending, {} errors{}",
+            creator.creator,
+            creator.post_count,
+            creator.downloaded,
+            creator.pending,
+            creator.errors,
+            dates
+        );
+    }
 
     Ok(())
 }
 
+/// Logs to the console with `--log` and to a daily rotated file with `--log-file`.
+fn init_logging(args: &Args) -> Result<Option<WorkerGuard>> {
+    let console = args.log.then(tracing_subscriber::fmt::layer);
+    let (file, guard) = match &args.log_file {
+        Some(path) => {
+            let Some(file_name) = path.file_name() else {
+                bail!("log file `{path}` is not a file name");
+            };
+            let directory = match path.parent() {
+                Some(parent) if !parent.as_str().is_empty() => parent,
+                _ => Utf8Path::new("."),
+            };
+            std::fs::create_dir_all(directory)?;
+            let appender = RollingFileAppender::builder()
+                .rotation(Rotation::DAILY)
+                .filename_prefix(file_name)
+                .build(directory)?;
+            let (writer, guard) = tracing_appender::non_blocking(appender);
+            let layer = tracing_subscriber::fmt::layer()
+                .with_ansi(false)
+                .with_writer(writer);
+            (Some(layer), Some(guard))
+        }
+        None => (None, None),
+    };
+
+    if console.is_some() || file.is_some() {
+        tracing_subscriber::registry()
+            .with(EnvFilter::new("info"))
+            .with(console)
+            .with(file)
+            .init();
+    }
+    Ok(guard)
+}
+
 #[tokio::main]
 async fn main() -> Result<()> {
     color_eyre::install()?;
     let args = Args::parse();
 
-    if args.log {
-        tracing_subscriber::fmt()
-            .with_env_filter(EnvFilter::new("info"))
-            .init();
-    }
+    // flushes the log file when dropped, which `process::exit` skips, so it's dropped before that
+    let log_guard = init_logging(&args)?;
 
-    let config = Configuration::load()?;
-    let pool = SqlitePool::connect("sqlite:hutt.sqlite3").await?;
-    let context = DownloadContext {
-        database: Database::new(pool),
-        client: Client::new(),
-        configuration: config.clone(),
+    if let Some(data_dir) = &args.data_dir {
+        std::fs::create_dir_all(data_dir)?;
+    }
+    let default_config_path = args.data_path("config.json5");
+    if let Command::Init { interactive } = args.command {
+        let args = InitArgs {
+            config_path: args.config.clone().unwrap_or(default_config_path),
+            database_path: args.data_path("hutt.sqlite3"),
+            interactive,
+        };
+        return commands::init::run(args).await;
+    }
+    let mut config = match &args.config {
+        Some(path) => Configuration::load_file(path)?,
+        None if !args.command.needs_configuration()
+            && Configuration::find(&default_config_path).is_none() =>
+        {
+            Configuration::default()
+        }
+        None => Configuration::load(&default_config_path)?,
     };
+    if args.data_dir.is_some() {
+        config.download_directory = Some(args.data_path(config.download_directory()));
+    }
+    if let Some(source) = config.cookie_source {
+        match source.load() {
+            Ok(cookie) => config.cookie = cookie,
+            Err(e) => {
+                warn!("{e}, using the configured cookie instead");
+                // yt-dlp would fail the same way, so it gets the configured cookie as well
+                config.cookie_source = None;
+            }
+        }
+    }
+    if let Some(archive) = &config.yt_dlp_archive {
+        // yt-dlp runs inside the download directory, so the path has to be absolute
+        let current_dir = Utf8PathBuf::try_from(std::env::current_dir()?)?;
+        config.yt_dlp_archive = Some(current_dir.join(args.data_path(archive)));
+    }
+    if let Some(output_dir) = args.command.output_dir() {
+        let current_dir = Utf8PathBuf::try_from(std::env::current_dir()?)?;
+        config.download_directory = Some(current_dir.join(output_dir));
+    }
+    let database_path = args.data_path("hutt.sqlite3");
+    let checkpoint_path = args.data_path(".hutt-scrape-state");
+    let pool = database::connect(
+        &database_path,
+        config.database_synchronous.unwrap_or_default(),
+    )
+    .await?;
+    let context = DownloadContext::new(pool, config.clone())?;
+    let notifier = Notifier::new(context.client.clone(), &config, args.notify);
 
     info!("Running with args: {:?}", args);
 
     match args.command {
-        Command::Metadata {} => {
-            commands::metadata::run(
+        Command::Metadata {
+            empty_page_tolerance,
+            save_html,
+            batch_size,
+            skip,
+            only_new,
+            full,
+            max_pages,
+            deadline,
+            with_comments,
+            refetch_metadata,
+            since_page,
+            liked,
+        } => {
+            let started = Instant::now();
+            let only_new =
+                (only_new || config.only_new.unwrap_or(false)) && !full && !refetch_metadata;
+            let result = commands::metadata::run(
+                context,
+                MetadataArgs {
+                    creator_id: config.creator_id,
+                    creator_name: config.creator_name,
+                    cookie: config.cookie,
+                    empty_page_tolerance,
+                    save_html,
+                    progress: !args.log,
+                    batch_size,
+                    skip_type: skip,
+                    only_new,
+                    max_pages,
+                    deadline,
+                    with_comments,
+                    refetch_metadata,
+                    start_page: since_page,
+                    // the checkpoint belongs to the creator's feed
+                    checkpoint: (!liked).then_some(checkpoint_path),
+                    resume: !full && !refetch_metadata,
+                    liked,
+                },
+            )
+            .await;
+            let summary = match &result {
+                Ok(stats) => {
+                    let counts = RunCounts {
+                        processed: stats.scraped,
+                        new: stats.new,
+                        skipped: stats.skipped,
+                        errors: stats.failed,
+                    };
+                    RunSummary::new("metadata", started.elapsed(), counts, None)
+                }
+                Err(e) => RunSummary::new(
+                    "metadata",
+                    started.elapsed(),
+                    RunCounts::default(),
+                    Some(e.to_string()),
+                ),
+            };
+            notifier.send(&summary).await;
+            result?;
+        }
+        Command::Rescan {
+            start,
+            end,
+            save_html,
+        } => {
+            if start > end {
+                bail!("the start page must not be after the end page");
+            }
+            commands::metadata::rescan(
                 context,
                 MetadataArgs {
                     creator_id: config.creator_id,
                     creator_name: config.creator_name,
                     cookie: config.cookie,
+                    empty_page_tolerance: 0,
+                    save_html,
+                    progress: false,
+                    batch_size: 1,
+                    skip_type: None,
+                    only_new: false,
+                    max_pages: None,
+                    deadline: None,
+                    with_comments: false,
+                    refetch_metadata: false,
+                    start_page: None,
+                    checkpoint: None,
+                    resume: false,
+                    liked: false,
                 },
+                start..=end,
             )
             .await?;
         }
-        Command::Download { dry_run } => {
-            commands::download::run(
+        Command::Download {
+            dry_run,
+            order,
+            keep_going,
+            max_size,
+            quota,
+            deadline,
+            check_updates,
+            json,
+            image_concurrency,
+            video_concurrency,
+            title_match,
+            title_exclude,
+            case_sensitive,
+            retry_errors,
+            sniff_content_type,
+            bucket_by,
+            accurate_progress,
+            select,
+            flatten,
+            ..
+        } => {
+            let title_filter = TitleFilter::new(
+                title_match.as_deref(),
+                title_exclude.as_deref(),
+                case_sensitive,
+            )?;
+            let started = Instant::now();
+            let result = commands::download::run(
                 context,
                 DownloadArgs {
                     filename_pattern: config.filename_pattern(),
                     path: config.download_directory().to_owned(),
                     dry_run,
-                    progress: !args.log,
-                    fail_fast: true,
+                    progress: !args.log && !json,
+                    fail_fast: !keep_going,
+                    order,
+                    max_size,
+                    quota,
+                    check_updates,
+                    json,
+                    image_concurrency,
+                    video_concurrency,
+                    title_filter,
+                    retry_errors,
+                    sniff_content_type,
+                    bucket_by,
+                    deadline,
+                    accurate_progress,
+                    select,
+                    flatten,
                 },
             )
-            .await?
+            .await;
+            let summary = match &result {
+                Ok(stats) => {
+                    let counts = RunCounts {
+                        processed: stats.processed,
+                        new: stats.downloaded,
+                        skipped: stats.processed - stats.downloaded - stats.errors,
+                        errors: stats.errors,
+                    };
+                    RunSummary::new("download", started.elapsed(), counts, None)
+                }
+                Err(e) => RunSummary::new(
+                    "download",
+                    started.elapsed(),
+                    RunCounts::default(),
+                    Some(e.to_string()),
+                ),
+            };
+            notifier.send(&summary).await;
+            let stats = result?;
+
+            if stats.errors > 0 {
+                eprintln!("{} links failed to download.", stats.errors);
+                drop(log_guard);
+                std::process::exit(PARTIAL_FAILURE_EXIT_CODE);
+            }
         }
-        Command::ResetDownloads => {
-            context.database.reset_downloads().await?;
+        Command::ResetDownloads { creator, post_type } => {
+            let scope = match (&creator, post_type) {
+                (None, None) => "all links".to_string(),
+                (Some(creator), None) => format!("links of {creator}"),
+                (None, Some(post_type)) => format!("{post_type:?} links"),
+                (Some(creator), Some(post_type)) => format!("{post_type:?} links of {creator}"),
+            };
+            confirm(&format!("reset the download status of {scope}"), args.yes)?;
+            let count = context
+                .database
+                .reset_downloads(creator.as_deref(), post_type)
+                .await?;
+            println!("Reset {count} links to pending.");
         }
         Command::BackupDatabase => {
             let backup_path = format!(
                 "hutt.{}.sqlite3",
                 chrono::Utc::now().format("%Y-%m-%d_%H-%M-%S")
             );
-            std::fs::copy("hutt.sqlite3", backup_path)?;
+            std::fs::copy(&database_path, database_path.with_file_name(backup_path))?;
+        }
+        Command::Report {
+            errors_only,
+            limit,
+            offset,
+        } => {
+            if errors_only {
+                print_errors(context, Page { limit, offset }).await?
+            } else {
+                print_report(context).await?
+            }
+        }
+        Command::Rename {
+            dry_run,
+            plan,
+            copy,
+            bucket_by,
+            preserve_structure,
+            ..
+        } => {
+            commands::rename::run(
+                context,
+                RenameArgs {
+                    dry_run,
+                    plan,
+                    copy,
+                    bucket_by,
+                    preserve_structure,
+                },
+            )
+            .await?;
+        }
+        Command::SetDates {
+            start,
+            end,
+            dry_run,
+            strategy,
+            curve,
+            force,
+        } => {
+            commands::set_dates::run(
+                context,
+                SetDatesArgs {
+                    start,
+                    end,
+                    dry_run,
+                    strategy,
+                    curve,
+                    force,
+                },
+            )
+            .await?;
+        }
+        Command::Import { other } => {
+            commands::import::run(context, ImportArgs { other }).await?;
+        }
+        Command::Reparse { dir } => {
+            commands::reparse::run(context, ReparseArgs { dir }).await?;
+        }
+        Command::Config { show } => {
+            config.validate()?;
+            if show {
+                println!("{}", serde_json::to_string_pretty(&config.effective())?);
+            } else {
+                println!("Configuration is valid.");
+            }
+        }
+        Command::ListCreators { limit, offset } => {
+            print_creators(context, Page { limit, offset }).await?;
+        }
+        Command::DiskUsage { top } => {
+            commands::disk_usage::run(context, DiskUsageArgs { top }).await?;
+        }
+        Command::ResolveCreator { url } => {
+            commands::resolve_creator::run(context, ResolveCreatorArgs { url }).await?;
+        }
+        Command::Doctor => {
+            if !commands::doctor::run(context).await? {
+                drop(log_guard);
+                std::process::exit(1);
+            }
         }
-        Command::Report => print_report(context).await?,
-        Command::Rename { dry_run } => {
-            commands::rename::run(dry_run, context).await?;
+        Command::DownloadProfile => {
+            commands::profile::run(context).await?;
         }
-        Command::SetDates { start, end } => {
-            commands::set_dates::run(context, SetDatesArgs { start, end }).await?;
+        Command::Manifest { output, verify } => {
+            commands::manifest::run(context, ManifestArgs { output, verify }).await?;
         }
+        Command::Init { .. } => unreachable!("init runs before the configuration is loaded"),
     }
     Ok(())
 }
@@ -0,0 +1,138 @@
+use std::time::Duration;
+
+use reqwest::Client;
+use serde::Serialize;
+
+use crate::Configuration;
+
+/// What a run got through. Posts for `metadata`, links for `download`.
+#[derive(Debug, Default, Clone, Copy, Serialize)]
+pub struct RunCounts {
+    pub processed: usize,
+    /// New posts that were stored or files that were downloaded.
+    pub new: usize,
+    pub skipped: usize,
+    pub errors: usize,
+}
+
+/// Summary of a finished run, sent as JSON to the webhook.
+#[derive(Debug, Serialize)]
+#[serde(rename_all = "camelCase")]
+pub struct RunSummary {
+    pub command: &'static str,
+    pub success: bool,
+    pub duration_seconds: u64,
+    #[serde(flatten)]
+    pub counts: RunCounts,
+    pub message: String,
+}
+
+impl RunSummary {
+    pub fn new(
+        command: &'static str,
+        duration: Duration,
+        counts: RunCounts,
+        error: Option<String>,
+    ) -> Self {
+        let message = match &error {
+            Some(error) => format!("{command} failed after {}s: {error}", duration.as_secs()),
+            None => format!(
+                "{command} finished in {}s: {} processed, {} new, {} skipped, {} errors",
+                duration.as_secs(),
+                counts.processed,
+                counts.new,
+                counts.skipped,
+                counts.errors
+            ),
+        };
+        Self {
+            command,
+            success: error.is_none(),
+            duration_seconds: duration.as_secs(),
+            counts,
+            message,
+        }
+    }
+}
+
+/// Sends a notification when a long-running command finishes.
+pub struct Notifier {
+    client: Client,
+    webhook: Option<String>,
+    desktop: bool,
+}
+
+impl Notifier {
+    pub fn new(client: Client, configuration: &Configuration, desktop: bool) -> Self {
+        Self {
+            client,
+            webhook: configuration.notify_webhook.clone(),
+            desktop: desktop || configuration.notify.unwrap_or(false),
+        }
+    }
+
+    /// Sends the summary to all configured targets. Failing to notify never fails the run.
+    pub async fn send(&self, summary: &RunSummary) {
+        if self.desktop {
+            show_desktop_notification(summary);
+        }
+
+        if let Some(webhook) = &self.webhook {
+            let result = self
+                .client
+                .post(webhook)
+                .json(summary)
+                .send()
+                .await
+                .and_then(|response| response.error_for_status());
+            if let Err(e) = result {
+                eprintln!("Failed to send notification to webhook: {e}");
+            }
+        }
+    }
+}
+
+#[cfg(feature = "desktop-notifications")]
+fn show_desktop_notification(summary: &RunSummary) {
+    let result = notify_rust::Notification::new()
+        .summary("hutt-archiver")
+        .body(&summary.message)
+        .show();
+    if let Err(e) = result {
+        eprintln!("Failed to show desktop notification: {e}");
+    }
+}
+
+#[cfg(not(feature = "desktop-notifications"))]
+fn show_desktop_notification(_summary: &RunSummary) {
+    eprintln!(
+        "Desktop notifications are not available in this build, enable the `desktop-notifications` feature."
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::{RunCounts, RunSummary};
+
+    #[test]
+    fn test_run_summary() {
+        let counts = RunCounts {
+            processed: 12,
+            new: 5,
+            skipped: 6,
+            errors: 1,
+        };
+        let summary = RunSummary::new("metadata", Duration::from_secs(90), counts, None);
+        assert_eq!(
+            summary.message,
+            "metadata finished in 90s: 12 processed, 5 new, 6 skipped, 1 errors"
+        );
+
+        let json = serde_json::to_value(&summary).unwrap();
+        assert_eq!(json["durationSeconds"], 90);
+        assert_eq!(json["processed"], 12);
+        assert_eq!(json["errors"], 1);
+    }
+}
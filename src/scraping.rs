@@ -0,0 +1,880 @@
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use camino::{Utf8Path, Utf8PathBuf};
+use color_eyre::eyre::eyre;
+use reqwest::Url;
+use scraper::{ElementRef, Html, Selector};
+use serde::Deserialize;
+use tracing::{info, warn};
+
+use crate::database::{CreateComment, CreatePost, CreatePostLink, LinkSource, PostType};
+use crate::{Configuration, Result};
+
+#[derive(Deserialize)]
+struct GalleryImage {
+    src: Option<String>,
+    html: Option<String>,
+}
+
+#[derive(Default)]
+struct UrlExtractor {
+    failed_galleries: AtomicUsize,
+}
+
+/// A single `<source>` candidate found in an embedded player.
+struct VideoSource {
+    url: String,
+    content_type: String,
+    resolution: u32,
+}
+
+impl VideoSource {
+    /// Higher is better: prefer higher resolutions, then widely supported formats.
+    fn score(&self) -> (u32, u32) {
+        let format = match self.content_type.as_str() {
+            "video/mp4" => 3,
+            "video/webm" => 2,
+            "video/quicktime" => 1,
+            _ => 0,
+        };
+        (self.resolution, format)
+    }
+}
+
+/// The content type of a video URL based on its extension, if it has a known one.
+fn media_content_type(url: &str) -> Option<&'static str> {
+    let path = url.split(['?', '#']).next().unwrap_or(url);
+    let extension = path
+        .rsplit_once('.')
+        .map(|(_, ext)| ext.to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("mp4" | "m4v") => Some("video/mp4"),
+        Some("webm") => Some("video/webm"),
+        Some("mov") => Some("video/quicktime"),
+        Some("m3u8") => Some("application/x-mpegURL"),
+        _ => None,
+    }
+}
+
+fn content_type_from_extension(url: &str) -> &'static str {
+    media_content_type(url).unwrap_or("video/mp4")
+}
+
+fn parse_resolution(value: &str) -> Option<u32> {
+    let digits: String = value
+        .chars()
+        .skip_while(|c| !c.is_ascii_digit())
+        .take_while(|c| c.is_ascii_digit())
+        .collect();
+    digits.parse().ok()
+}
+
+/// Finds the array assigned to `dynamicEl` by matching brackets, skipping over string contents.
+fn find_gallery_json(script: &str) -> Option<&str> {
+    let key = script.find("dynamicEl")?;
+    let start = key + script[key..].find('[')?;
+
+    let mut depth = 0;
+    let mut quote = None;
+    let mut escaped = false;
+    for (index, c) in script[start..].char_indices() {
+        if let Some(q) = quote {
+            if escaped {
+                escaped = false;
+            } else if c == '\\' {
+                escaped = true;
+            } else if c == q {
+                quote = None;
+            }
+            continue;
+        }
+
+        match c {
+            '"' | '\'' => quote = Some(c),
+            '[' | '{' => depth += 1,
+            ']' | '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(&script[start..=start + index]);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    None
+}
+
+/// Drops backslashes in front of characters that aren't valid JSON escapes (e.g. `\>`),
+/// which the embedded JavaScript uses but `serde_json` rejects.
+fn unescape_gallery_json(json: &str) -> String {
+    let mut result = String::with_capacity(json.len());
+    let mut chars = json.chars().peekable();
+    while let Some(c) = chars.next() {
+        if c == '\\' {
+            match chars.next() {
+                Some(next @ ('"' | '\\' | '/' | 'b' | 'f' | 'n' | 'r' | 't' | 'u')) => {
+                    result.push(c);
+                    result.push(next);
+                }
+                Some(next) => result.push(next),
+                None => result.push(c),
+            }
+        } else {
+            result.push(c);
+        }
+    }
+    result
+}
+
+impl UrlExtractor {
+    fn video_source(&self, element: ElementRef) -> Option<VideoSource> {
+        let url = element.attr("src")?.trim();
+        if url.is_empty() {
+            return None;
+        }
+        let content_type = element
+            .attr("type")
+            .and_then(|t| t.split(';').next())
+            .map(|t| t.trim().to_ascii_lowercase())
+            .filter(|t| !t.is_empty())
+            .unwrap_or_else(|| content_type_from_extension(url).to_string());
+        let resolution = ["res", "size", "data-res", "data-quality", "label", "title"]
+            .iter()
+            .filter_map(|attr| element.attr(attr))
+            .find_map(parse_resolution)
+            .unwrap_or_default();
+
+        Some(VideoSource {
+            url: url.to_string(),
+            content_type,
+            resolution,
+        })
+    }
+
+    /// Finds the video in an embedded HTML blob. `<video>` and `<source>` tags win over other
+    /// elements, which are only considered if their URL looks like a video file. This way poster
+    /// and thumbnail images aren't mistaken for the video.
+    fn parse_url_from_html(&self, html: &str) -> Option<CreatePostLink> {
+        let fragment = Html::parse_fragment(html);
+        let sources = Selector::parse("video[src], source[src]").unwrap();
+        let mut candidates: Vec<VideoSource> = fragment
+            .select(&sources)
+            .filter_map(|e| self.video_source(e))
+            .filter(|source| !source.content_type.starts_with("image/"))
+            .collect();
+        if candidates.is_empty() {
+            let any_src = Selector::parse("[src]").unwrap();
+            candidates.extend(
+                fragment
+                    .select(&any_src)
+                    .filter(|e| e.attr("src").and_then(media_content_type).is_some())
+                    .filter_map(|e| self.video_source(e)),
+            );
+        }
+
+        if candidates.len() > 1 {
+            info!("found {} video sources in html string", candidates.len());
+        }
+
+        candidates
+            .into_iter()
+            .max_by_key(|source| source.score())
+            .map(|source| CreatePostLink {
+                url: source.url,
+                content_type: source.content_type,
+                source: LinkSource::HtmlString,
+            })
+    }
+
+    fn extract_urls(&self, element: ElementRef, post_type: PostType) -> Vec<CreatePostLink> {
+        match post_type {
+            PostType::Image => {
+                let selector = Selector::parse("script").unwrap();
+                let script_el = element
+                    .select(&selector)
+                    .map(|e| e.text().collect::<String>())
+                    .find(|script| script.contains("dynamicEl"));
+                let Some(script_el) = script_el else {
+                    warn!("failed to find gallery script element");
+                    self.failed_galleries.fetch_add(1, Ordering::Relaxed);
+                    return Vec::new();
+                };
+                if let Some(gallery_json) = find_gallery_json(&script_el) {
+                    let gallery_json = unescape_gallery_json(gallery_json);
+                    match serde_json::from_str::<Vec<GalleryImage>>(&gallery_json) {
+                        Ok(json) => {
+                            let mut post_links = Vec::new();
+                            for image in json {
+                                if let Some(src) = image.src {
+                                    post_links.push(CreatePostLink {
+                                        url: src,
+                                        content_type: "image/jpeg".to_string(),
+                                        source: LinkSource::ImageGallery,
+                                    });
+                                }
+                                if let Some(url) =
+                                    image.html.and_then(|html| self.parse_url_from_html(&html))
+                                {
+                                    post_links.push(url);
+                                }
+                            }
+                            post_links
+                        }
+                        Err(e) => {
+                            warn!("failed to parse gallery json: {gallery_json}: {e:?}");
+                            self.failed_galleries.fetch_add(1, Ordering::Relaxed);
+                            Vec::new()
+                        }
+                    }
+                } else {
+                    warn!(
+                        "failed to find gallery json in script element {}",
+                        script_el
+                    );
+                    self.failed_galleries.fetch_add(1, Ordering::Relaxed);
+                    Vec::new()
+                }
+            }
+            PostType::Video => {
+                let selector = Selector::parse("video source").unwrap();
+
+                if let Some(source_element) = element.select(&selector).next() {
+                    vec![CreatePostLink {
+                        url: source_element.attr("src").unwrap().to_string(),
+                        content_type: "video/mp4".to_string(),
+                        source: LinkSource::VideoPost,
+                    }]
+                } else {
+                    warn!("failed to find video source element");
+                    Vec::new()
+                }
+            }
+        }
+    }
+}
+
+/// Strips the leading `#` of tags, lowercases them and removes duplicates, keeping the order in
+/// which they first appear.
+pub fn normalize_tags(raw_tags: &[String]) -> Vec<String> {
+    let mut seen = HashSet::new();
+    raw_tags
+        .iter()
+        .map(|tag| tag.trim().trim_start_matches('#').trim().to_lowercase())
+        .filter(|tag| !tag.is_empty() && seen.insert(tag.clone()))
+        .collect()
+}
+
+/// Buttons and links like "Read more" that Hutt puts into the post text.
+fn is_ui_element(element: &scraper::node::Element) -> bool {
+    const UI_CLASSES: &[&str] = &["read-more", "show-more", "more-link", "btn"];
+
+    matches!(element.name(), "button" | "script" | "style" | "svg")
+        || element.attr("role") == Some("button")
+        || element.classes().any(|class| UI_CLASSES.contains(&class))
+}
+
+/// The text of an element without the text of any UI elements nested in it.
+fn visible_text(element: ElementRef) -> String {
+    let mut text = String::new();
+    for node in element.descendants() {
+        let Some(fragment) = node.value().as_text() else {
+            continue;
+        };
+        let in_ui_element = node
+            .ancestors()
+            .take_while(|ancestor| ancestor.id() != element.id())
+            .filter_map(|ancestor| ancestor.value().as_element())
+            .any(is_ui_element);
+        if !in_ui_element {
+            text.push_str(fragment);
+        }
+    }
+    text.trim().to_string()
+}
+
+/// Query parameters that only track where a link was clicked and don't change the content.
+const TRACKING_PARAMS: &[&str] = &["fbclid", "gclid", "ref", "_"];
+
+/// Normalizes a media URL so that variants of the same link compare equal: tracking parameters
+/// and fragments are removed, the host is lowercased and hutt.co URLs are made relative, like
+/// the ones in the gallery JSON. The remaining query is kept byte for byte, so signed URLs stay
+/// valid.
+pub(crate) fn normalize_url(url: &str) -> String {
+    let base = Url::parse("https://hutt.co/").unwrap();
+    let Ok(mut parsed) = base.join(url.trim()) else {
+        return url.trim().to_string();
+    };
+    parsed.set_fragment(None);
+
+    let query: Vec<&str> = parsed
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|pair| {
+            let key = pair.split_once('=').map_or(*pair, |(key, _)| key);
+            !key.is_empty() && !key.starts_with("utm_") && !TRACKING_PARAMS.contains(&key)
+        })
+        .collect();
+    let query = (!query.is_empty()).then(|| query.join("&"));
+    parsed.set_query(query.as_deref());
+
+    match parsed.host_str() {
+        Some("hutt.co" | "www.hutt.co") => match parsed.query() {
+            Some(query) => format!("{}?{}", parsed.path(), query),
+            None => parsed.path().to_string(),
+        },
+        _ => parsed.to_string(),
+    }
+}
+
+/// Normalizes the URLs of a post's links and drops the ones that point to the same media.
+fn dedupe_links(links: Vec<CreatePostLink>) -> Vec<CreatePostLink> {
+    let mut seen = HashSet::new();
+    links
+        .into_iter()
+        .map(|link| CreatePostLink {
+            url: normalize_url(&link.url),
+            ..link
+        })
+        .filter(|link| seen.insert(link.url.clone()))
+        .collect()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SkipReason {
+    MissingId,
+    InvalidId,
+    MissingPostType,
+    NoLinks,
+    InsertFailed,
+    MissingAuthor,
+}
+
+impl SkipReason {
+    pub fn description(&self) -> &'static str {
+        match self {
+            SkipReason::MissingId => "no post id",
+            SkipReason::InvalidId => "invalid post id",
+            SkipReason::MissingPostType => "no post type",
+            SkipReason::NoLinks => "no links",
+            SkipReason::InsertFailed => "failed to insert",
+            SkipReason::MissingAuthor => "no author",
+        }
+    }
+}
+
+/// A post that was found on a page but couldn't be turned into a `CreatePost`.
+pub struct SkippedPost {
+    pub post_id: Option<String>,
+    pub reason: SkipReason,
+}
+
+#[derive(Default)]
+pub struct ScrapedPage {
+    pub posts: Vec<CreatePost>,
+    pub skipped: Vec<SkippedPost>,
+    /// Comments of the scraped posts, only filled in when `PostScraper::with_comments` is set.
+    pub comments: Vec<CreateComment>,
+}
+
+pub struct Selectors {
+    post_wrapper: Selector,
+    like_count: Selector,
+    title: Selector,
+    tags: Selector,
+    video_element: Selector,
+    image_element: Selector,
+    pinned: Selector,
+    comment: Selector,
+    comment_author: Selector,
+    comment_text: Selector,
+    author: Selector,
+}
+
+fn parse_selector(selector: Option<&str>, default: &str) -> Result<Selector> {
+    let selector = selector.unwrap_or(default);
+    Selector::parse(selector).map_err(|e| eyre!("invalid selector `{selector}`: {e}"))
+}
+
+impl Selectors {
+    pub fn from_config(configuration: &Configuration) -> Result<Self> {
+        let config = configuration.selectors.clone().unwrap_or_default();
+        Ok(Self {
+            post_wrapper: parse_selector(config.post_wrapper.as_deref(), ".huttPost.has-media")?,
+            like_count: parse_selector(config.like_count.as_deref(), ".likes-count")?,
+            title: parse_selector(config.title.as_deref(), ".post-text")?,
+            tags: parse_selector(config.tags.as_deref(), ".tags a.label")?,
+            video_element: parse_selector(config.video_element.as_deref(), "figure.hutt-video")?,
+            image_element: parse_selector(config.image_element.as_deref(), ".img-responsive")?,
+            pinned: parse_selector(
+                config.pinned.as_deref(),
+                ".pinned, .is-pinned, .pinned-post, .featured",
+            )?,
+            comment: parse_selector(config.comment.as_deref(), ".comment")?,
+            comment_author: parse_selector(config.comment_author.as_deref(), ".comment-author")?,
+            comment_text: parse_selector(config.comment_text.as_deref(), ".comment-text")?,
+            author: parse_selector(config.author.as_deref(), ".post-author")?,
+        })
+    }
+}
+
+/// Turns the HTML of a page of posts into `CreatePost`s, independent of how it was fetched.
+pub struct PostScraper {
+    selectors: Selectors,
+    url_extractor: UrlExtractor,
+    pub skip_type: Option<PostType>,
+    /// Where the raw HTML of every post is saved, if at all.
+    pub html_directory: Option<Utf8PathBuf>,
+    /// Whether to scrape the comments of each post as well.
+    pub with_comments: bool,
+    /// Takes the creator of each post from the post itself instead of using the configured one,
+    /// for feeds with posts of several creators.
+    pub post_authors: bool,
+}
+
+impl PostScraper {
+    pub fn new(selectors: Selectors) -> Self {
+        Self {
+            selectors,
+            url_extractor: UrlExtractor::default(),
+            skip_type: None,
+            html_directory: None,
+            with_comments: false,
+            post_authors: false,
+        }
+    }
+
+    fn extract_post_type(&self, element: ElementRef) -> Option<PostType> {
+        let video = element.select(&self.selectors.video_element).next();
+        if video.is_some() {
+            return Some(PostType::Video);
+        }
+        let image = element.select(&self.selectors.image_element).next();
+        if image.is_some() {
+            return Some(PostType::Image);
+        }
+        None
+    }
+
+    fn extract_title(&self, element: ElementRef) -> String {
+        element
+            .select(&self.selectors.title)
+            .next()
+            .map(visible_text)
+            .unwrap_or_else(|| "Untitled".into())
+    }
+
+    fn extract_body(&self, element: ElementRef) -> Option<String> {
+        element
+            .select(&self.selectors.title)
+            .next()
+            .map(|e| e.inner_html().trim().to_string())
+            .filter(|body| !body.is_empty())
+    }
+
+    /// Whether the post is pinned, either by a class on the wrapper or a badge inside it.
+    fn is_pinned(&self, element: ElementRef) -> bool {
+        self.selectors.pinned.matches(&element)
+            || element.select(&self.selectors.pinned).next().is_some()
+    }
+
+    /// The tags as they appear on the page.
+    fn extract_tags(&self, element: ElementRef) -> Vec<String> {
+        let elements = element.select(&self.selectors.tags);
+        let mut tags = vec![];
+        for tag_el in elements {
+            let tag: String = tag_el.text().collect();
+            let tag = tag.trim().to_string();
+            if !tag.is_empty() {
+                tags.push(tag);
+            }
+        }
+
+        tags
+    }
+
+    /// The comments shown with the post. Comments that Hutt only loads on scrolling or behind a
+    /// "show more" button aren't part of the page, so they are missed.
+    fn extract_comments(&self, element: ElementRef, post_id: i64) -> Vec<CreateComment> {
+        element
+            .select(&self.selectors.comment)
+            .filter_map(|comment| {
+                let text = comment
+                    .select(&self.selectors.comment_text)
+                    .next()
+                    .map(visible_text)
+                    .filter(|text| !text.is_empty())?;
+                let author = comment
+                    .select(&self.selectors.comment_author)
+                    .next()
+                    .map(visible_text)
+                    .unwrap_or_default();
+                Some(CreateComment {
+                    post_id,
+                    author,
+                    text,
+                })
+            })
+            .collect()
+    }
+
+    /// The name of the post's creator, without a leading `@`.
+    fn extract_author(&self, element: ElementRef) -> Option<String> {
+        element
+            .select(&self.selectors.author)
+            .next()
+            .map(visible_text)
+            .map(|author| author.trim_start_matches('@').to_string())
+            .filter(|author| !author.is_empty())
+    }
+
+    fn save_html(&self, directory: &Utf8Path, element: ElementRef, id: &str) -> Result<()> {
+        std::fs::create_dir_all(directory)?;
+        // `post-123` is saved as `123.html`
+        let name = id.strip_prefix("post-").unwrap_or(id);
+        let path = directory.join(format!("{}.html", sanitize_filename::sanitize(name)));
+        std::fs::write(&path, element.html())?;
+        Ok(())
+    }
+
+    /// How many image galleries couldn't be parsed so far.
+    pub fn failed_galleries(&self) -> usize {
+        self.url_extractor.failed_galleries.load(Ordering::Relaxed)
+    }
+
+    /// Scrapes all posts in `text`. `page` is the page of the feed it came from, if known.
+    pub fn scrape_posts(&self, text: String, creator_name: &str, page: Option<u32>) -> ScrapedPage {
+        let document = scraper::Html::parse_document(&text);
+
+        let mut result = ScrapedPage::default();
+
+        for element in document.select(&self.selectors.post_wrapper) {
+            if let Some(raw_id) = element.attr("id") {
+                if let Some(directory) = &self.html_directory {
+                    if let Err(e) = self.save_html(directory, element, raw_id) {
+                        warn!("failed to save html of post {raw_id}: {e}");
+                    }
+                }
+                let skip = |reason| SkippedPost {
+                    post_id: Some(raw_id.to_string()),
+                    reason,
+                };
+                let id = raw_id.replace("post-", "");
+                let id: i64 = match id.parse() {
+                    Ok(id) => id,
+                    Err(e) => {
+                        warn!("Invalid post id {raw_id}: {e}, skipping");
+                        result.skipped.push(skip(SkipReason::InvalidId));
+                        continue;
+                    }
+                };
+                info!("Scraping post {id}");
+                let post_type = self.extract_post_type(element);
+                if post_type.is_none() {
+                    warn!("No post type found for post {id}, skipping");
+                    result.skipped.push(skip(SkipReason::MissingPostType));
+                    continue;
+                }
+                let post_type = post_type.unwrap();
+                if self.skip_type == Some(post_type) {
+                    info!("Post {id} is a {post_type:?} post, skipping");
+                    continue;
+                }
+                let links = dedupe_links(self.url_extractor.extract_urls(element, post_type));
+                if links.is_empty() {
+                    info!("No links found for post {id}, skipping");
+                    result.skipped.push(skip(SkipReason::NoLinks));
+                    continue;
+                } else {
+                    info!("Found {} links for post {id}", links.len());
+                }
+                let creator = if self.post_authors {
+                    match self.extract_author(element) {
+                        Some(author) => author,
+                        None => {
+                            warn!("No author found for post {id}, skipping");
+                            result.skipped.push(skip(SkipReason::MissingAuthor));
+                            continue;
+                        }
+                    }
+                } else {
+                    creator_name.to_string()
+                };
+                let title = self.extract_title(element);
+                let body = self.extract_body(element);
+                let raw_tags = self.extract_tags(element);
+                let like_count: Option<String> = element
+                    .select(&self.selectors.like_count)
+                    .next()
+                    .map(|e| e.text().collect());
+                let like_count: i64 = like_count.and_then(|s| s.parse().ok()).unwrap_or_default();
+                if self.with_comments {
+                    result.comments.extend(self.extract_comments(element, id));
+                }
+
+                result.posts.push(CreatePost {
+                    id,
+                    like_count,
+                    post_type,
+                    tags: normalize_tags(&raw_tags),
+                    raw_tags,
+                    pinned: self.is_pinned(element),
+                    links,
+                    title,
+                    creator,
+                    source_page: page.map(Into::into),
+                    body,
+                })
+            } else {
+                info!("No id found for post, skipping");
+                result.skipped.push(SkippedPost {
+                    post_id: None,
+                    reason: SkipReason::MissingId,
+                });
+            }
+        }
+
+        result
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        find_gallery_json, normalize_tags, normalize_url, unescape_gallery_json, PostScraper,
+        Selectors, SkipReason, UrlExtractor,
+    };
+    use crate::database::{CreateComment, PostType};
+    use crate::Configuration;
+
+    fn scraper() -> PostScraper {
+        PostScraper::new(Selectors::from_config(&Configuration::test()).unwrap())
+    }
+
+    #[test]
+    fn test_scrape_image_posts() {
+        let html = include_str!("../fixtures/image_posts.html");
+        let page = scraper().scrape_posts(html.to_string(), "creator", Some(3));
+
+        assert!(page.skipped.is_empty());
+        assert_eq!(page.posts.len(), 1);
+        let post = &page.posts[0];
+        assert_eq!(post.id, 1001);
+        assert_eq!(post.post_type, PostType::Image);
+        assert_eq!(post.title, "Beach day photos!");
+        assert_eq!(post.tags, vec!["beach", "summer"]);
+        assert_eq!(post.raw_tags, vec!["#beach", "summer"]);
+        assert!(!post.pinned);
+        assert_eq!(post.like_count, 42);
+        assert_eq!(post.creator, "creator");
+        assert_eq!(post.source_page, Some(3));
+
+        let urls: Vec<_> = post.links.iter().map(|link| link.url.as_str()).collect();
+        assert_eq!(
+            urls,
+            vec![
+                "/images/5001/big",
+                "/images/5002/big",
+                "/videos/5003-720.mp4"
+            ]
+        );
+        assert_eq!(post.links[2].content_type, "video/mp4");
+    }
+
+    #[test]
+    fn test_scrape_video_posts() {
+        let html = include_str!("../fixtures/video_posts.html");
+        let page = scraper().scrape_posts(html.to_string(), "creator", Some(0));
+
+        assert_eq!(page.posts.len(), 1);
+        let post = &page.posts[0];
+        assert_eq!(post.id, 1002);
+        assert_eq!(post.post_type, PostType::Video);
+        assert_eq!(post.title, "New video is up");
+        assert_eq!(post.tags, vec!["vlog"]);
+        assert_eq!(post.like_count, 7);
+        assert_eq!(post.links.len(), 1);
+        assert_eq!(post.links[0].url, "/videos/6001.mp4");
+
+        let skipped: Vec<_> = page
+            .skipped
+            .iter()
+            .map(|post| (post.post_id.as_deref(), post.reason))
+            .collect();
+        assert_eq!(
+            skipped,
+            vec![
+                (Some("post-1003"), SkipReason::MissingPostType),
+                (None, SkipReason::MissingId),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrape_title_with_nested_ui() {
+        let html = include_str!("../fixtures/nested_title.html");
+        let page = scraper().scrape_posts(html.to_string(), "creator", None);
+
+        assert_eq!(page.posts.len(), 1);
+        let title: Vec<_> = page.posts[0].title.split_whitespace().collect();
+        assert_eq!(title.join(" "), "Sunset photoset from the pier");
+    }
+
+    #[test]
+    fn test_scrape_comments() {
+        let html = include_str!("../fixtures/comments.html");
+        let page = scraper().scrape_posts(html.to_string(), "creator", None);
+        assert!(page.comments.is_empty());
+
+        let mut scraper = scraper();
+        scraper.with_comments = true;
+        let page = scraper.scrape_posts(html.to_string(), "creator", None);
+        assert_eq!(page.posts.len(), 1);
+        assert_eq!(
+            page.comments,
+            vec![
+                CreateComment {
+                    post_id: 1003,
+                    author: "fan".into(),
+                    text: "Looks great!".into(),
+                },
+                CreateComment {
+                    post_id: 1003,
+                    author: "".into(),
+                    text: "No author on this one".into(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_scrape_post_authors() {
+        let html = include_str!("../fixtures/liked_posts.html");
+        let page = scraper().scrape_posts(html.to_string(), "creator", None);
+        assert_eq!(page.posts.len(), 3);
+        assert!(page.posts.iter().all(|post| post.creator == "creator"));
+
+        let mut scraper = scraper();
+        scraper.post_authors = true;
+        let page = scraper.scrape_posts(html.to_string(), "creator", None);
+        let creators: Vec<_> = page
+            .posts
+            .iter()
+            .map(|post| (post.id, post.creator.as_str()))
+            .collect();
+        assert_eq!(creators, vec![(2001, "someone"), (2002, "another")]);
+        assert_eq!(page.skipped.len(), 1);
+        assert_eq!(page.skipped[0].reason, SkipReason::MissingAuthor);
+    }
+
+    #[test]
+    fn test_normalize_tags() {
+        let raw_tags: Vec<String> = ["#Cosplay", "cosplay", " #Beach ", "beach", "#", "Summer"]
+            .into_iter()
+            .map(ToOwned::to_owned)
+            .collect();
+        assert_eq!(
+            normalize_tags(&raw_tags),
+            vec!["cosplay", "beach", "summer"]
+        );
+    }
+
+    #[test]
+    fn test_normalize_url() {
+        assert_eq!(normalize_url("/images/1234/big"), "/images/1234/big");
+        assert_eq!(
+            normalize_url("https://HUTT.co/images/1234/big?utm_source=feed#top"),
+            "/images/1234/big"
+        );
+        assert_eq!(
+            normalize_url("https://www.hutt.co/images/1234/big?fbclid=abc&size=2"),
+            "/images/1234/big?size=2"
+        );
+        assert_eq!(
+            normalize_url("https://CDN.example.com/videos/1.mp4?token=abc&_=123"),
+            "https://cdn.example.com/videos/1.mp4?token=abc"
+        );
+        // the rest of the query isn't re-encoded
+        assert_eq!(
+            normalize_url("https://cdn.example.com/1.mp4?sig=a~b%3D%3D&utm_medium=x&t=a%20b"),
+            "https://cdn.example.com/1.mp4?sig=a~b%3D%3D&t=a%20b"
+        );
+    }
+
+    #[test]
+    fn test_gallery_json_with_commas_and_brackets() {
+        let script = r#"$(el).lightGallery({
+            dynamic: true,
+            dynamicEl: [{"src": "/a.jpg", "subHtml": "a, b ] c"}, {"src": "/b.jpg"}],
+            index: 0,
+        });"#;
+        let json = find_gallery_json(script).unwrap();
+        assert_eq!(
+            json,
+            r#"[{"src": "/a.jpg", "subHtml": "a, b ] c"}, {"src": "/b.jpg"}]"#
+        );
+    }
+
+    #[test]
+    fn test_gallery_json_invalid_escapes() {
+        let json = unescape_gallery_json(r#"[{"html": "<video\> \"x\" <\/video>"}]"#);
+        assert_eq!(json, r#"[{"html": "<video> \"x\" <\/video>"}]"#);
+    }
+
+    #[test]
+    fn test_html_string_single_source() {
+        let html = r#"<video><source src="/videos/1.mp4"></video>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.url, "/videos/1.mp4");
+        assert_eq!(link.content_type, "video/mp4");
+    }
+
+    #[test]
+    fn test_html_string_picks_best_source() {
+        let html = r#"<video>
+            <source src="/videos/1-480.webm" type="video/webm" res="480">
+            <source src="/videos/1-1080.mp4" type="video/mp4" res="1080">
+            <source src="/videos/1-720.mp4" type="video/mp4" res="720">
+        </video>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.url, "/videos/1-1080.mp4");
+    }
+
+    #[test]
+    fn test_html_string_content_type_from_extension() {
+        let html = r#"<video><source src="/videos/1.webm?token=abc"></video>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.content_type, "video/webm");
+    }
+
+    #[test]
+    fn test_html_string_skips_poster_image() {
+        let html = r#"<div class="player">
+            <img src="/images/1/poster.jpg">
+            <video poster="/images/1/poster.jpg"><source src="/videos/1.mov"></video>
+        </div>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.url, "/videos/1.mov");
+        assert_eq!(link.content_type, "video/quicktime");
+    }
+
+    #[test]
+    fn test_html_string_video_src_attribute() {
+        let html = r#"<img src="/images/1/thumb.jpg"><video src="/videos/1.webm"></video>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.url, "/videos/1.webm");
+        assert_eq!(link.content_type, "video/webm");
+    }
+
+    #[test]
+    fn test_html_string_other_elements_need_media_extension() {
+        let html = r#"<img src="/images/1/poster.jpg"><iframe src="/embed/1.mp4"></iframe>"#;
+        let link = UrlExtractor::default().parse_url_from_html(html).unwrap();
+        assert_eq!(link.url, "/embed/1.mp4");
+
+        let html = r#"<img src="/images/1/poster.jpg">"#;
+        assert!(UrlExtractor::default().parse_url_from_html(html).is_none());
+    }
+}